@@ -29,6 +29,12 @@
 //! # Ok(()) }
 //! ```
 //!
+//! Headers passed to [Archive::add_entry] are written as is, so they must be
+//! able to represent the entry. Use [Archive::add_entry_with_metadata], or
+//! [Archive::add_entry_with_builder] with a [HeaderBuilder], to have tario
+//! build the header and write a PAX extended header ahead of it for any
+//! fields that don't fit, such as long paths.
+//!
 //! # Reading
 //!
 //! When reading an archive, use [Archive::next_entry] to get an [Entry]
@@ -74,30 +80,59 @@
 //! entry1;
 //! // error[E0499]: cannot borrow `archive` as mutable more than once at a time
 //! ```
+//!
+//! Errors are returned as [std::io::Error]s. Those caused by the archive
+//! being malformed or truncated wrap an [ArchiveError], which tells what
+//! went wrong and where. To get what is left of a damaged archive instead,
+//! see [Archive::set_resync].
 
 use std::borrow::Cow;
 use std::future::poll_fn;
-use std::io::Result;
+use std::io::{Error, Result};
 use std::num::NonZeroUsize;
+use std::path::Path;
 use std::pin::Pin;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use pin_project_lite::pin_project;
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite};
 
 mod shared;
-pub use shared::block::{BLOCK_SIZE, Header};
+pub use shared::block::{BLOCK_SIZE, ChecksumKind, EntryType, Header};
+#[cfg(feature = "compression")]
+pub use shared::compression::Compression;
+pub use shared::kind::HeaderKind;
+pub use shared::pax::PaxAttributes;
+pub use shared::sparse::SparseRegion;
 
 mod read;
-pub use read::ReadError;
+#[cfg(feature = "compression")]
+pub use read::Decoder;
+pub use read::{
+    ArchiveError, DamagedRegion, ErrorLocation, HeaderField, IndexEntry, ReadError, ReadPhase,
+};
+#[cfg(feature = "fs")]
+pub use read::{PathPolicy, UnpackError, UnpackSummary, Unpacked};
 
 mod write;
-pub use write::WriteError;
+#[cfg(feature = "compression")]
+pub use write::Encoder;
+pub use write::{EntryMetadata, Format, HeaderBuilder, WriteError};
 
 #[cfg(feature = "streams")]
 use read::Entries;
+use read::Extensions;
 use read::NextEntry;
+use read::Resync;
+use read::Seeker;
+use read::UnpackOptions;
+use shared::block::{Block, header_mtime};
 use shared::buffer::Buf;
+use shared::ext::EntryExtensions;
+use shared::kind;
+use shared::poison::Poison;
 use shared::state::State;
+use write::AppendOptions;
 
 const DEFAULT_BUFFER_CAPACITY: usize = 8; // x512 = 4k
 
@@ -108,6 +143,21 @@ pin_project! {
     pub struct Archive<T> {
         buf: Buf,
         state: State,
+        ext: Extensions,
+        format: Format,
+        reproducible: Option<SystemTime>,
+        flush_entries: bool,
+        ignore_zeros: bool,
+        allow_missing_trailer: bool,
+        trailer_complete: bool,
+        seek: Seeker<T>,
+        pos: u64,
+        header_pos: Option<u64>,
+        entries: u64,
+        poison: Option<Poison>,
+        resync: Resync,
+        unpack: UnpackOptions,
+        append: AppendOptions,
 
         #[pin]
         io: T,
@@ -137,19 +187,78 @@ impl<T> Archive<T> {
         Self {
             buf: Buf::new(cap),
             state: State::default(),
+            ext: Extensions::default(),
+            format: Format::default(),
+            reproducible: None,
+            flush_entries: false,
+            ignore_zeros: false,
+            allow_missing_trailer: false,
+            trailer_complete: false,
+            seek: Seeker::default(),
+            pos: 0,
+            header_pos: None,
+            entries: 0,
+            poison: None,
+            resync: Resync::default(),
+            unpack: UnpackOptions::default(),
+            append: AppendOptions::default(),
             io,
         }
     }
 
+    /// Returns the offset in the archive of the next byte to be read or
+    /// written. Offsets start from where the underlying I/O object was
+    /// positioned when the archive was created, and count bytes written
+    /// into the archive buffer even if they haven't been flushed yet.
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Returns a reference to the underlying I/O object.
+    pub fn get_ref(&self) -> &T {
+        &self.io
+    }
+
     /// Consumes this archive and returns the underlying I/O object.
     pub fn into_inner(self) -> T {
         self.io
     }
 }
 
+#[cfg(feature = "compression")]
+impl<R: AsyncRead + Unpin> Archive<Decoder<R>> {
+    /// Creates a new Archive with default buffer capacity, that detects
+    /// whether `io` is compressed from its first bytes and decompresses it
+    /// if so.
+    ///
+    /// gzip, zstd, xz and bzip2 are detected, and decompressed if the
+    /// feature of the same name is enabled. Plain archives are read as is.
+    /// See [Decoder] for details.
+    ///
+    /// ```no_run
+    /// # use std::io::Result;
+    /// # #[tokio::main(flavor = "current_thread")] async fn main() -> Result<()> {
+    /// use tario::Archive;
+    ///
+    /// let io = tokio::fs::File::open("archive.tar.gz").await?;
+    /// let mut archive = Archive::new_auto(io);
+    /// while let Some(mut entry) = archive.next_entry().await? {
+    ///     entry.skip().await?;
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn new_auto(io: R) -> Self {
+        Self::new(Decoder::new(io))
+    }
+}
+
 impl<R: AsyncRead + Unpin> Archive<R> {
     /// Returns a future that resolves to the next [entry][Entry] or [None]
     /// if EOF is reached.
+    ///
+    /// The previous entry must have been read to its end or
+    /// [skipped][Entry::skip], unless it has no data. Otherwise this fails
+    /// with [ReadError::OverlappingEntry], leaving the archive unusable.
     #[inline]
     pub fn next_entry(&mut self) -> NextEntry<'_, R> {
         NextEntry::new(self)
@@ -182,19 +291,357 @@ impl<R: AsyncRead + Unpin> Archive<R> {
     pub fn entries(&mut self) -> Entries<'_, R> {
         Entries::new(self)
     }
+
+    /// Returns the attributes of the PAX global extended headers read so
+    /// far. These apply to every subsequent entry unless overridden.
+    #[inline]
+    pub fn global_attributes(&self) -> &PaxAttributes {
+        self.ext.global()
+    }
+
+    /// Sets the maximum size of extension entries, such as PAX extended
+    /// headers and GNU long names, that precede an entry. Their data is
+    /// buffered in memory so reading fails with
+    /// [ArchiveError::ExtensionTooLarge] if an extension, or the sparse map
+    /// of an entry, exceeds this size.
+    ///
+    /// The default is 1 MiB.
+    #[inline]
+    pub fn set_max_extension_size(&mut self, max: u64) {
+        self.ext.set_max_size(max);
+    }
+
+    /// Sets whether empty blocks in place of headers are skipped, rather
+    /// than taken as the end-of-archive marker, so that archives
+    /// concatenated together are read as one. Reading then ends only when
+    /// the source does, which must be at the start of a header.
+    ///
+    /// The default is false.
+    #[inline]
+    pub fn set_ignore_zeros(&mut self, enabled: bool) {
+        self.ignore_zeros = enabled;
+    }
+
+    /// Sets whether the source may end without the end-of-archive marker,
+    /// or with only part of it, as long as it ends between entries. Use
+    /// [Self::has_complete_trailer] to tell whether it did.
+    ///
+    /// The default is false.
+    #[inline]
+    pub fn set_allow_missing_trailer(&mut self, enabled: bool) {
+        self.allow_missing_trailer = enabled;
+    }
+
+    /// Returns whether the end-of-archive marker has been read in full,
+    /// which is only the case once [Self::next_entry] returns [None] and
+    /// the source didn't end before the marker did.
+    ///
+    /// This is always false when [empty blocks are ignored][Self::set_ignore_zeros].
+    #[inline]
+    pub fn has_complete_trailer(&self) -> bool {
+        self.trailer_complete
+    }
+
+    /// Enables skipping over damaged headers instead of failing, for
+    /// recovering what is left of a damaged archive. When a header is
+    /// malformed, blocks are skipped until one is a valid header or is
+    /// empty, and `on_damaged` is called with the region skipped before
+    /// reading goes on from there. A damaged region that runs to the end of
+    /// the source ends the archive.
+    ///
+    /// Any extension entries preceding a damaged header are skipped along
+    /// with it. Errors in entry data and in the end-of-archive marker are
+    /// still returned.
+    ///
+    /// ```no_run
+    /// # use std::io::Result;
+    /// # #[tokio::main(flavor = "current_thread")] async fn main() -> Result<()> {
+    /// use tario::Archive;
+    ///
+    /// let io = tokio::fs::File::open("damaged.tar").await?;
+    /// let mut archive = Archive::new(io);
+    /// archive.set_resync(|region| {
+    ///     eprintln!("skipped {} bytes at {}: {}", region.len, region.offset, region.error);
+    /// });
+    /// while let Some(mut entry) = archive.next_entry().await? {
+    ///     entry.skip().await?;
+    /// }
+    /// # Ok(()) }
+    /// ```
+    pub fn set_resync<F>(&mut self, on_damaged: F)
+    where
+        F: FnMut(DamagedRegion) + Send + Sync + 'static,
+    {
+        self.resync.set_callback(Box::new(on_damaged));
+    }
+
+    /// Sets what [Self::unpack] and [Entry::unpack_in] do with entries
+    /// whose paths point outside of the destination directory.
+    ///
+    /// The default is [PathPolicy::Reject].
+    ///
+    /// This is only available when the `fs` feature is enabled.
+    #[cfg(feature = "fs")]
+    #[inline]
+    pub fn set_path_policy(&mut self, policy: PathPolicy) {
+        self.unpack.policy = policy;
+    }
+
+    /// Unpacks every entry of this archive into the `dst` directory, which
+    /// is created if missing, and returns a summary of what was written.
+    ///
+    /// Regular files, directories, symlinks, hard links and FIFOs are
+    /// unpacked with the mode bits and mtime recorded in the archive; other
+    /// entries are skipped. Directory metadata is applied after all entries
+    /// are unpacked, so that read-only directories can still be filled.
+    ///
+    /// Every path is checked to stay within `dst`, including through
+    /// symlinks unpacked by earlier entries, and entries that don't are
+    /// handled according to the [path policy][Self::set_path_policy].
+    ///
+    /// This is only available when the `fs` feature is enabled.
+    #[cfg(feature = "fs")]
+    pub async fn unpack<P: AsRef<Path>>(&mut self, dst: P) -> Result<UnpackSummary> {
+        read::unpack_archive(self, dst.as_ref()).await
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> Archive<R> {
+    /// Sets whether [Entry::skip] seeks past the data of entries, rather
+    /// than reading through it, so that listing an archive only reads its
+    /// headers. Skipping still reads through what is already buffered.
+    ///
+    /// Seeks are relative, so the source may be positioned anywhere as long
+    /// as the archive starts there.
+    ///
+    /// Seeking is opt-in: the default is false, even for sources that can
+    /// seek, and only [Self::index] and [Self::open_entry] seek otherwise.
+    #[inline]
+    pub fn set_seekable(&mut self, enabled: bool) {
+        self.seek.set_skip(enabled);
+    }
+
+    /// Reads the headers of the remaining entries in the archive, seeking
+    /// past their data, and returns where each one is. Whether
+    /// [Entry::skip] seeks is left [as it was][Self::set_seekable].
+    ///
+    /// The index can be saved with [IndexEntry::encode] and passed to
+    /// [Self::open_entry] later, to read entries without reading through
+    /// the archive.
+    pub async fn index(&mut self) -> Result<Vec<IndexEntry>> {
+        read::index_archive(self).await
+    }
+
+    /// Seeks to the entry recorded in the given index entry and returns it.
+    /// Whether [Entry::skip] seeks is left [as it was][Self::set_seekable].
+    ///
+    /// This fails with [ReadError::IndexMismatch] if the entry found there
+    /// doesn't match the index entry, as it would if the index is of another
+    /// archive. Global PAX attributes apply to the entry if they have been
+    /// read, such as while indexing, but are not recorded in the index.
+    pub async fn open_entry(&mut self, entry: &IndexEntry) -> Result<Entry<'_, R>> {
+        read::open_entry(self, entry).await
+    }
 }
 
 impl<W: AsyncWrite + Unpin> Archive<W> {
+    /// Sets the format extension used by [Self::add_entry_with_metadata] to
+    /// write entry metadata that doesn't fit in a ustar header.
+    ///
+    /// The default is [Format::Pax].
+    #[inline]
+    pub fn set_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    /// Sets the archive to be written reproducibly, so that the same
+    /// entries make the same archive whenever and wherever it is written.
+    ///
+    /// Every entry added is normalised before its header is written: mtimes
+    /// later than `source_date_epoch` are clamped to it, rounded down to the
+    /// second, owner ids and names are zeroed, modes become 0o755 for
+    /// directories and executables, 0o777 for symlinks and 0o644 for
+    /// anything else, and access and change times are dropped. Entries must
+    /// still be added in a deterministic order, as [Self::append_dir_all]
+    /// does. Adding an entry whose mtime cannot be read fails with
+    /// [WriteError::InvalidMtime], leaving the archive unusable.
+    ///
+    /// `source_date_epoch` is usually taken from the `SOURCE_DATE_EPOCH`
+    /// environment variable, as described in the [Reproducible Builds
+    /// specification][1]:
+    ///
+    /// ```
+    /// # use std::io::Result;
+    /// # fn main() -> Result<()> {
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use tario::Archive;
+    ///
+    /// let mut archive = Archive::new(Vec::new());
+    /// let epoch = std::env::var("SOURCE_DATE_EPOCH")
+    ///     .ok()
+    ///     .and_then(|secs| secs.parse().ok())
+    ///     .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    /// archive.set_reproducible(epoch);
+    /// # Ok(()) }
+    /// ```
+    ///
+    /// Passing [None] turns this off, which is the default.
+    ///
+    /// [1]: https://reproducible-builds.org/specs/source-date-epoch/
+    #[inline]
+    pub fn set_reproducible(&mut self, source_date_epoch: Option<SystemTime>) {
+        self.reproducible = source_date_epoch;
+    }
+
+    /// Writes `header` and returns an [Entry] handle for writing the data
+    /// of the entry.
+    ///
+    /// This fails with [WriteError::UnfinalizedHeader] if the checksum of
+    /// the header has not been set, leaving the archive unusable.
     #[inline]
     pub async fn add_entry(&mut self, header: Header) -> Result<Entry<'_, W>> {
+        self.add_entry_with_extensions(header, EntryExtensions::default())
+            .await
+    }
+
+    /// Writes the header of an entry with the given path, size and metadata,
+    /// and returns an [Entry] handle for writing its data.
+    ///
+    /// Unlike [Self::add_entry], this takes care of fields that don't fit
+    /// in a ustar header, such as paths longer than 100 bytes or sizes over
+    /// 8 GiB, by writing extension entries ahead of the entry according to
+    /// the archive [format][Self::set_format].
+    pub async fn add_entry_with_metadata<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        size: u64,
+        meta: &EntryMetadata,
+    ) -> Result<Entry<'_, W>> {
+        let (header, ext) = write::encode(path.as_ref(), size, meta, self.format)?;
+        self.add_entry_with_extensions(header, ext).await
+    }
+
+    /// Writes the header built by `builder`, preceded by any extension
+    /// entries it needs, and returns an [Entry] handle for writing its data.
+    ///
+    /// Unless the builder has a format set, the archive
+    /// [format][Self::set_format] is used.
+    pub async fn add_entry_with_builder(
+        &mut self,
+        builder: &HeaderBuilder,
+    ) -> Result<Entry<'_, W>> {
+        let (header, ext) = builder.encode(self.format)?;
+        self.add_entry_with_extensions(header, ext).await
+    }
+
+    /// Writes the header of a sparse entry with the given path, data regions
+    /// and file size, and returns an [Entry] handle for writing the data of
+    /// the regions, back to back and in order. Anything outside the regions
+    /// is a hole that takes no space in the archive.
+    ///
+    /// Sparse entries are written in the PAX 1.0 sparse format of GNU tar,
+    /// whatever the archive [format][Self::set_format]. Readers that don't
+    /// understand it extract the entry data as is, under a made-up path.
+    pub async fn add_sparse_entry<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        regions: &[SparseRegion],
+        size: u64,
+        meta: &EntryMetadata,
+    ) -> Result<Entry<'_, W>> {
+        let (header, ext, map) = write::encode_sparse(path.as_ref(), regions, size, meta)?;
+        let mut entry = self.add_entry_with_extensions(header, ext).await?;
+
+        // The sparse map is the first part of the entry data.
+        let mut pos = 0;
+        while pos < map.len() {
+            let n = poll_fn(|cx| Pin::new(&mut entry).poll_write(cx, &map[pos..])).await?;
+            if n == 0 {
+                return WriteError::WriteZero.into();
+            }
+            pos += n;
+        }
+        entry.data_offset += map.len() as u64;
+        Ok(entry)
+    }
+
+    /// Sets whether [Self::append_dir_all] follows symlinks and archives
+    /// what they point to, or archives them as symlinks.
+    ///
+    /// The default is to follow symlinks.
+    ///
+    /// This is only available when the `fs` feature is enabled.
+    #[cfg(feature = "fs")]
+    #[inline]
+    pub fn set_follow_symlinks(&mut self, follow: bool) {
+        self.append.follow_symlinks = follow;
+    }
+
+    /// Appends the directory at `src_path` and everything in it to the
+    /// archive, under `path`. If `path` is empty, the contents of the
+    /// directory are appended at the root of the archive.
+    ///
+    /// Entries are written in a deterministic order, each directory
+    /// followed by its contents sorted by name. Their metadata comes from
+    /// the filesystem, including owner names where they can be looked up,
+    /// and files with more than one link are written as hard links to the
+    /// first path they were found at. Sockets are left out, as are device
+    /// files on platforms other than Linux.
+    ///
+    /// This is only available when the `fs` feature is enabled.
+    #[cfg(feature = "fs")]
+    pub async fn append_dir_all<P, Q>(&mut self, path: P, src_path: Q) -> Result<()>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        write::append_dir_all(self, path.as_ref(), src_path.as_ref()).await
+    }
+
+    /// Writes the extension entries for `ext` followed by `header`, after
+    /// normalising them if the archive is [reproducible][Self::set_reproducible].
+    async fn add_entry_with_extensions(
+        &mut self,
+        mut header: Header,
+        mut ext: EntryExtensions,
+    ) -> Result<Entry<'_, W>> {
         let mut pin = Pin::new(self);
+
+        pin.check_poison()?;
+        if !header.cksum().is_ok_and(|cksum| cksum > 0) {
+            return Err(pin.poison(WriteError::UnfinalizedHeader));
+        }
+        if let Some(epoch) = pin.reproducible {
+            write::normalize(&mut header, &mut ext, epoch)
+                .map_err(|err| pin.as_mut().poison(err))?;
+        }
+
+        if pin.flush_entries {
+            poll_fn(|cx| pin.as_mut().poll_flush(cx)).await?;
+        }
+
+        let header_offset = pin.pos;
+
+        for (ext_header, data) in write::extension_entries(&header, &ext) {
+            poll_fn(|cx| pin.as_mut().poll_write_header(cx, &ext_header)).await?;
+            poll_fn(|cx| pin.as_mut().poll_write_extension(cx, &data)).await?;
+        }
+
         poll_fn(|cx| pin.as_mut().poll_write_header(cx, &header)).await?;
-        Entry::new(pin, header)
+        let data_offset = pin.pos;
+        let index = pin.entries;
+        pin.entries += 1;
+        let mut entry = Entry::new(pin, header, ext)?;
+        entry.header_offset = header_offset;
+        entry.data_offset = data_offset;
+        entry.index = index;
+        Ok(entry)
     }
 
     /// Writes the last two consecutive empty blocks that signify EOF.
     ///
-    /// This will panic if an entry is currently being written.
+    /// This fails with [WriteError::UnfinishedEntry] if an entry is
+    /// currently being written, leaving the archive unusable.
     #[inline]
     pub async fn finish(&mut self) -> Result<()> {
         let mut pin = Pin::new(self);
@@ -202,6 +649,57 @@ impl<W: AsyncWrite + Unpin> Archive<W> {
     }
 }
 
+#[cfg(feature = "compression")]
+impl<W: AsyncWrite + Unpin> Archive<Encoder<W>> {
+    /// Creates a new Archive with default buffer capacity, that compresses
+    /// what it writes into `io` with the given compression.
+    ///
+    /// This fails with [std::io::ErrorKind::Unsupported] if the feature of the
+    /// same name as the compression is not enabled. See [Encoder] for details.
+    ///
+    /// ```
+    /// # use std::io::Result;
+    /// # #[tokio::main(flavor = "current_thread")] async fn main() -> Result<()> {
+    /// use tario::{Archive, Compression, HeaderBuilder};
+    ///
+    /// # let compression = Compression::None;
+    /// let mut archive = Archive::new_compressed(Vec::new(), compression)?;
+    /// archive.set_frame_per_entry(true);
+    /// archive.add_entry_with_builder(&HeaderBuilder::new("empty")).await?;
+    /// archive.finish().await?;
+    /// # Ok(()) }
+    /// ```
+    pub fn new_compressed(io: W, compression: Compression) -> Result<Self> {
+        Ok(Self::new(Encoder::new(io, compression)?))
+    }
+
+    /// Sets whether each entry is compressed in a frame of its own, or
+    /// member in gzip terms, so that entries can be decompressed
+    /// independently from the start of their frame. Defaults to false.
+    ///
+    /// Frames are ended before each entry is added, and whenever the archive
+    /// or an entry is flushed. [Self::end_frame] returns where the frame of
+    /// the next entry starts.
+    pub fn set_frame_per_entry(&mut self, enabled: bool) {
+        self.flush_entries = enabled;
+        self.io.set_frame_per_flush(enabled);
+    }
+
+    /// Ends the current frame, writing everything added so far to the
+    /// underlying writer, and returns the offset of the next frame in the
+    /// compressed stream.
+    ///
+    /// Calling this before adding an entry gives the offset the entry can
+    /// be decompressed from, whether or not [frames are per
+    /// entry][Self::set_frame_per_entry].
+    pub async fn end_frame(&mut self) -> Result<u64> {
+        let mut pin = Pin::new(self);
+        poll_fn(|cx| pin.as_mut().poll_flush_buffered(cx)).await?;
+        poll_fn(|cx| pin.io.poll_end_frame(cx)).await?;
+        Ok(pin.io.position())
+    }
+}
+
 pin_project! {
     /// A handle to a file entry in a TAR archive, that provides methods to
     /// read or write its data.
@@ -209,17 +707,34 @@ pin_project! {
     pub struct Entry<'a, T> {
         archive: Pin<&'a mut Archive<T>>,
         header: Header,
+        ext: EntryExtensions,
+        size: u64,
+        len: u64,
+        header_offset: u64,
+        data_offset: u64,
+        index: u64,
     }
 }
 
 impl<'a, T> Entry<'a, T> {
-    fn new(archive: Pin<&'a mut Archive<T>>, header: Header) -> Result<Self> {
-        let cksum = header.cksum()?;
-        assert!(cksum > 0, "header must be finalized before creating entry");
-
-        let _ = header.size()?;
+    fn new(archive: Pin<&'a mut Archive<T>>, header: Header, ext: EntryExtensions) -> Result<Self> {
+        // The extensions of the entry have been taken, so trying again would
+        // make an entry without them.
+        let (size, len) = match archive.entry_lens(&header, &ext) {
+            Ok(lens) => lens,
+            Err(err) => return Err(archive.poison(err)),
+        };
 
-        Ok(Self { archive, header })
+        Ok(Self {
+            archive,
+            header,
+            ext,
+            size,
+            len,
+            header_offset: 0,
+            data_offset: 0,
+            index: 0,
+        })
     }
 
     /// Returns the header of this entry.
@@ -227,16 +742,35 @@ impl<'a, T> Entry<'a, T> {
         &self.header
     }
 
-    /// Returns the file size of this entry.
+    /// Returns the PAX extended attributes that apply to this entry,
+    /// including any global ones in effect.
+    pub fn pax_attributes(&self) -> &PaxAttributes {
+        &self.ext.pax
+    }
+
+    /// Returns the offset in the archive of the first header of this entry,
+    /// which is that of any extension entries preceding it, such as PAX
+    /// extended headers or GNU long names. See [Archive::position].
+    pub fn header_offset(&self) -> u64 {
+        self.header_offset
+    }
+
+    /// Returns the offset in the archive of the data of this entry. For
+    /// sparse entries, this is the offset of the first data region, after
+    /// any sparse map.
+    pub fn data_offset(&self) -> u64 {
+        self.data_offset
+    }
+
+    /// Returns the file size of this entry. For sparse entries, this is the
+    /// size of the file including its holes.
     pub fn size(&self) -> u64 {
-        // This cannot fail because we'd have already errored in [Self::new].
-        self.header.size().unwrap()
+        self.size
     }
 
     /// Returns the number of bytes this entry occupies in the archive.
     pub fn len(&self) -> u64 {
-        // This cannot fail because we'd have already errored in [Self::new].
-        self.header.entry_size().unwrap()
+        self.len
     }
 
     /// Returns whether this entry has no data.
@@ -244,15 +778,201 @@ impl<'a, T> Entry<'a, T> {
         self.len() == 0
     }
 
+    /// Returns the data regions of this entry if it is a sparse file, such
+    /// as those written by GNU tar with `--sparse`. Reading the entry fills
+    /// in the holes between them with zeros; writers that can make holes
+    /// can use this to skip over them instead.
+    pub fn sparse_map(&self) -> Option<&[SparseRegion]> {
+        self.ext.sparse.as_ref().map(|sparse| &*sparse.regions)
+    }
+
     /// Returns the pathname of this entry, with any `\` characters converted
     /// to directory separators.
-    pub fn path(&self) -> Cow<[u8]> {
-        self.header.path_bytes()
+    pub fn path(&self) -> Cow<'_, [u8]> {
+        entry_path(&self.header, &self.ext)
+    }
+
+    /// Returns how the checksum of the header of this entry was computed.
+    /// Some historical implementations summed bytes as signed, which only
+    /// makes a difference for headers with bytes over 0x7f, such as UTF-8
+    /// paths.
+    pub fn checksum_kind(&self) -> Result<ChecksumKind> {
+        Block::from_bytes(self.header.as_bytes()).checksum_kind()
+    }
+
+    /// Returns the format of this entry. Entries with PAX extended headers
+    /// of their own are [HeaderKind::Pax] whatever their header, unlike
+    /// those only affected by [global ones][Archive::global_attributes].
+    pub fn kind(&self) -> HeaderKind {
+        if self.ext.local_pax {
+            HeaderKind::Pax
+        } else {
+            self.header_kind()
+        }
+    }
+
+    /// Returns the format of the header of this entry, ignoring extensions.
+    pub fn header_kind(&self) -> HeaderKind {
+        HeaderKind::detect(&self.header)
     }
 
     /// Gets the path in a "lossy" way; only useful for reference.
     pub fn path_lossy(&self) -> String {
-        String::from_utf8_lossy(&self.header.path_bytes()).to_string()
+        String::from_utf8_lossy(&self.path()).to_string()
+    }
+
+    /// Returns the link name of this entry, if any.
+    pub fn link_name(&self) -> Option<Cow<'_, [u8]>> {
+        match self.ext.pax.link_path().or(self.ext.long_link.as_deref()) {
+            Some(path) => Some(Cow::Borrowed(path)),
+            None => self.header.link_name_bytes(),
+        }
+    }
+
+    /// Returns the owner user id of this entry.
+    pub fn uid(&self) -> Result<u64> {
+        match self
+            .ext
+            .pax
+            .uid()
+            .map_err(|_| self.extension_error("uid"))?
+        {
+            Some(uid) => Ok(uid),
+            None => self
+                .header
+                .uid()
+                .map_err(|_| self.field_error(HeaderField::Uid)),
+        }
+    }
+
+    /// Returns the owner group id of this entry.
+    pub fn gid(&self) -> Result<u64> {
+        match self
+            .ext
+            .pax
+            .gid()
+            .map_err(|_| self.extension_error("gid"))?
+        {
+            Some(gid) => Ok(gid),
+            None => self
+                .header
+                .gid()
+                .map_err(|_| self.field_error(HeaderField::Gid)),
+        }
+    }
+
+    /// Returns the owner user name of this entry, if any.
+    pub fn username(&self) -> Option<&[u8]> {
+        self.ext
+            .pax
+            .username()
+            .or_else(|| self.header.username_bytes())
+    }
+
+    /// Returns the owner group name of this entry, if any.
+    pub fn groupname(&self) -> Option<&[u8]> {
+        self.ext
+            .pax
+            .groupname()
+            .or_else(|| self.header.groupname_bytes())
+    }
+
+    /// Returns the access time of this entry, if recorded. Only PAX, GNU and
+    /// star entries can have one.
+    pub fn atime(&self) -> Result<Option<SystemTime>> {
+        let atime = self.ext.pax.atime();
+        if let Some(atime) = atime.map_err(|_| self.extension_error("atime"))? {
+            return Ok(Some(atime));
+        }
+        let secs = match self.header_kind() {
+            HeaderKind::Star => kind::star_atime(&self.header),
+            HeaderKind::Gnu => kind::gnu_atime(&self.header),
+            _ => Ok(None),
+        };
+        self.header_time(secs, HeaderField::Atime)
+    }
+
+    /// Returns the status change time of this entry, if recorded. Only PAX,
+    /// GNU and star entries can have one.
+    pub fn ctime(&self) -> Result<Option<SystemTime>> {
+        let ctime = self.ext.pax.ctime();
+        if let Some(ctime) = ctime.map_err(|_| self.extension_error("ctime"))? {
+            return Ok(Some(ctime));
+        }
+        let secs = match self.header_kind() {
+            HeaderKind::Star => kind::star_ctime(&self.header),
+            HeaderKind::Gnu => kind::gnu_ctime(&self.header),
+            _ => Ok(None),
+        };
+        self.header_time(secs, HeaderField::Ctime)
+    }
+
+    /// Returns the modification time of this entry.
+    ///
+    /// This has sub-second precision if the entry carries a PAX `mtime`.
+    pub fn mtime(&self) -> Result<SystemTime> {
+        let mtime = self.ext.pax.mtime();
+        if let Some(mtime) = mtime.map_err(|_| self.extension_error("mtime"))? {
+            return Ok(mtime);
+        }
+        header_mtime(&self.header)
+            .ok()
+            .and_then(unix_time)
+            .ok_or_else(|| self.field_error(HeaderField::Mtime))
+    }
+
+    /// Converts the seconds since the epoch read from `field` of the header
+    /// of this entry to a [SystemTime].
+    fn header_time(
+        &self,
+        secs: Result<Option<u64>>,
+        field: HeaderField,
+    ) -> Result<Option<SystemTime>> {
+        let Ok(secs) = secs else {
+            return Err(self.field_error(field));
+        };
+        secs.map(|secs| {
+            i64::try_from(secs)
+                .ok()
+                .and_then(unix_time)
+                .ok_or_else(|| self.field_error(field))
+        })
+        .transpose()
+    }
+
+    /// Returns the error for a malformed numeric field of the header of this
+    /// entry.
+    fn field_error(&self, field: HeaderField) -> Error {
+        let at = self.location();
+        ArchiveError::InvalidField { field, at }.into()
+    }
+
+    /// Returns the error for an invalid value of the PAX attribute `key`
+    /// of this entry.
+    fn extension_error(&self, key: &str) -> Error {
+        let key = Some(key.to_owned());
+        let at = self.location();
+        ArchiveError::InvalidExtension { key, at }.into()
+    }
+
+    fn location(&self) -> ErrorLocation {
+        ErrorLocation {
+            entry: self.index,
+            path: Some(self.path().into_owned()),
+            offset: self.header_offset,
+        }
+    }
+}
+
+/// Returns the path of the entry with the given header and extensions. See
+/// [Entry::path].
+fn entry_path<'a>(header: &'a Header, ext: &'a EntryExtensions) -> Cow<'a, [u8]> {
+    let sparse_name = ext.sparse.as_ref().and_then(|s| s.name.as_deref());
+    let path = sparse_name.or(ext.pax.path());
+    match path.or(ext.long_name.as_deref()) {
+        Some(path) => Cow::Borrowed(path),
+        None if HeaderKind::detect(header) == HeaderKind::Star => kind::star_path(header),
+        None => header.path_bytes(),
     }
 }
 
@@ -260,10 +980,30 @@ impl<R: AsyncRead + Unpin> Entry<'_, R> {
     /// Reads until the end of this entry. All entries must be fully consumed
     /// so this is necessary to call if you don't care about this entry's data
     /// and just need to skip to the next one.
+    ///
+    /// If the archive is [seekable][Archive::set_seekable], this seeks past
+    /// the data that is not buffered yet instead of reading it.
     #[inline]
     pub async fn skip(&mut self) -> Result<()> {
         let mut pin = Pin::new(self);
-        poll_fn(|cx| pin.as_mut().poll_skip(cx)).await
+        let seek = pin.archive.seek.skips();
+        poll_fn(|cx| pin.as_mut().poll_skip(cx, seek)).await
+    }
+
+    /// Unpacks this entry into the `dst` directory, as [Archive::unpack]
+    /// would, and returns what it was unpacked as. The entry data is copied
+    /// straight from the archive buffer, and the holes of sparse entries are
+    /// left as holes in the file.
+    ///
+    /// Hard link targets are resolved within `dst` and must already exist.
+    /// The parent directories of the entry are created if missing. Paths
+    /// that point outside of `dst` are handled according to the archive's
+    /// [path policy][Archive::set_path_policy].
+    ///
+    /// This is only available when the `fs` feature is enabled.
+    #[cfg(feature = "fs")]
+    pub async fn unpack_in<P: AsRef<Path>>(&mut self, dst: P) -> Result<Unpacked> {
+        read::unpack_in(self, dst.as_ref()).await
     }
 }
 
@@ -275,6 +1015,16 @@ impl<W: AsyncWrite + Unpin> Entry<'_, W> {
     }
 }
 
+/// Converts seconds since the epoch to a [SystemTime], if in range.
+fn unix_time(secs: i64) -> Option<SystemTime> {
+    let duration = Duration::from_secs(secs.unsigned_abs());
+    if secs < 0 {
+        UNIX_EPOCH.checked_sub(duration)
+    } else {
+        UNIX_EPOCH.checked_add(duration)
+    }
+}
+
 /// Re-export of [tar-rs][1] providing types for synchronous I/O.
 ///
 /// [1]: https://github.com/alexcrichton/tar-rs
@@ -293,16 +1043,34 @@ fn assert_autotraits() {
     fn is_unpin<T: Unpin>() {}
     is_unpin::<Archive<()>>();
     is_unpin::<Entry<()>>();
+    #[cfg(feature = "compression")]
+    is_unpin::<Decoder<()>>();
+    #[cfg(feature = "compression")]
+    is_unpin::<Encoder<()>>();
 
     fn is_send<T: Send>() {}
     is_send::<Archive<()>>();
     is_send::<Entry<()>>();
     is_send::<ReadError>();
+    is_send::<ArchiveError>();
     is_send::<WriteError>();
+    #[cfg(feature = "fs")]
+    is_send::<UnpackError>();
+    #[cfg(feature = "compression")]
+    is_send::<Decoder<()>>();
+    #[cfg(feature = "compression")]
+    is_send::<Encoder<()>>();
 
     fn is_sync<T: Sync>() {}
     is_sync::<Archive<()>>();
     is_sync::<Entry<()>>();
     is_sync::<ReadError>();
+    is_sync::<ArchiveError>();
     is_sync::<WriteError>();
+    #[cfg(feature = "fs")]
+    is_sync::<UnpackError>();
+    #[cfg(feature = "compression")]
+    is_sync::<Decoder<()>>();
+    #[cfg(feature = "compression")]
+    is_sync::<Encoder<()>>();
 }
@@ -0,0 +1,172 @@
+use std::io::{Cursor, Result};
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+#[cfg(feature = "bzip2")]
+use async_compression::tokio::bufread::BzDecoder;
+#[cfg(feature = "gzip")]
+use async_compression::tokio::bufread::GzipDecoder;
+#[cfg(feature = "xz")]
+use async_compression::tokio::bufread::XzDecoder;
+#[cfg(feature = "zstd")]
+use async_compression::tokio::bufread::ZstdDecoder;
+#[cfg(any(feature = "gzip", feature = "zstd", feature = "xz", feature = "bzip2"))]
+use tokio::io::BufReader;
+use tokio::io::{AsyncRead, AsyncReadExt, Chain, ReadBuf};
+
+use crate::TRACING_ENABLED;
+use crate::shared::compression::{Compression, MAGIC_LEN};
+
+/// The source stream, with the bytes read to detect its compression put
+/// back in front.
+type Source<R> = Chain<Cursor<Vec<u8>>, R>;
+
+/// A reader that detects the compression of the stream it wraps from its
+/// first bytes, and decompresses it if needed. Plain archives are passed
+/// through as is.
+///
+/// This is what [Archive::new_auto][crate::Archive::new_auto] reads from,
+/// and can be used directly to give the archive a different buffer
+/// capacity. Detecting a compression whose feature is not enabled fails with
+/// [ErrorKind::Unsupported][std::io::ErrorKind::Unsupported].
+#[derive(Debug)]
+pub struct Decoder<R> {
+    inner: Inner<R>,
+}
+
+#[derive(Debug)]
+enum Inner<R> {
+    Detecting {
+        io: R,
+        magic: [u8; MAGIC_LEN],
+        len: usize,
+    },
+    Plain(Source<R>),
+    // Decoders are boxed, as some are much larger than the source.
+    #[cfg(feature = "gzip")]
+    Gzip(Box<GzipDecoder<BufReader<Source<R>>>>),
+    #[cfg(feature = "zstd")]
+    Zstd(Box<ZstdDecoder<BufReader<Source<R>>>>),
+    #[cfg(feature = "xz")]
+    Xz(Box<XzDecoder<BufReader<Source<R>>>>),
+    #[cfg(feature = "bzip2")]
+    Bzip2(Box<BzDecoder<BufReader<Source<R>>>>),
+    /// Only while switching from detecting to decoding.
+    Switching,
+}
+
+impl<R> Decoder<R> {
+    /// Wraps the given reader. Nothing is read until the decoder is.
+    pub fn new(io: R) -> Self {
+        Self {
+            inner: Inner::Detecting {
+                io,
+                magic: [0; MAGIC_LEN],
+                len: 0,
+            },
+        }
+    }
+
+    /// Returns the compression of the stream, or [None] if nothing has been
+    /// read yet.
+    pub fn compression(&self) -> Option<Compression> {
+        match &self.inner {
+            Inner::Detecting { .. } | Inner::Switching => None,
+            Inner::Plain(_) => Some(Compression::None),
+            #[cfg(feature = "gzip")]
+            Inner::Gzip(_) => Some(Compression::Gzip),
+            #[cfg(feature = "zstd")]
+            Inner::Zstd(_) => Some(Compression::Zstd),
+            #[cfg(feature = "xz")]
+            Inner::Xz(_) => Some(Compression::Xz),
+            #[cfg(feature = "bzip2")]
+            Inner::Bzip2(_) => Some(Compression::Bzip2),
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> Decoder<R> {
+    /// Reads from the source until enough bytes are received to detect its
+    /// compression, then switches to the matching decoder.
+    fn poll_detect(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let Inner::Detecting { io, magic, len } = &mut self.inner else {
+            return Poll::Ready(Ok(()));
+        };
+
+        while *len < MAGIC_LEN {
+            let mut buf = ReadBuf::new(&mut magic[*len..]);
+            ready!(Pin::new(&mut *io).poll_read(cx, &mut buf))?;
+            let n = buf.filled().len();
+            if n == 0 {
+                break;
+            }
+            *len += n;
+        }
+
+        let compression = Compression::detect(&magic[..*len]).check_enabled()?;
+        if TRACING_ENABLED {
+            eprintln!("     |compression: {compression:?}");
+        }
+
+        let Inner::Detecting { io, magic, len } = mem::replace(&mut self.inner, Inner::Switching)
+        else {
+            unreachable!();
+        };
+        let source = Cursor::new(magic[..len].to_vec()).chain(io);
+
+        self.inner = match compression {
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => {
+                let mut decoder = Box::new(GzipDecoder::new(BufReader::new(source)));
+                decoder.multiple_members(true);
+                Inner::Gzip(decoder)
+            }
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let mut decoder = Box::new(ZstdDecoder::new(BufReader::new(source)));
+                decoder.multiple_members(true);
+                Inner::Zstd(decoder)
+            }
+            #[cfg(feature = "xz")]
+            Compression::Xz => {
+                let mut decoder = Box::new(XzDecoder::new(BufReader::new(source)));
+                decoder.multiple_members(true);
+                Inner::Xz(decoder)
+            }
+            #[cfg(feature = "bzip2")]
+            Compression::Bzip2 => {
+                let mut decoder = Box::new(BzDecoder::new(BufReader::new(source)));
+                decoder.multiple_members(true);
+                Inner::Bzip2(decoder)
+            }
+            // Anything else was rejected as not enabled above.
+            _ => Inner::Plain(source),
+        };
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Decoder<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_detect(cx))?;
+
+        match &mut this.inner {
+            Inner::Detecting { .. } | Inner::Switching => unreachable!(),
+            Inner::Plain(io) => Pin::new(io).poll_read(cx, buf),
+            #[cfg(feature = "gzip")]
+            Inner::Gzip(io) => Pin::new(io).poll_read(cx, buf),
+            #[cfg(feature = "zstd")]
+            Inner::Zstd(io) => Pin::new(io).poll_read(cx, buf),
+            #[cfg(feature = "xz")]
+            Inner::Xz(io) => Pin::new(io).poll_read(cx, buf),
+            #[cfg(feature = "bzip2")]
+            Inner::Bzip2(io) => Pin::new(io).poll_read(cx, buf),
+        }
+    }
+}
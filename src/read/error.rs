@@ -2,29 +2,46 @@ use std::fmt;
 use std::io::{Error as IoError, ErrorKind, Result};
 use std::task::Poll;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum ReadError {
-    UnexpectedEof { expected: usize, received: usize },
+    MalformedIndex,
+    IndexMismatch { offset: u64 },
+    OverlappingEntry,
+    Poisoned(Box<ReadError>),
 }
 
 impl ReadError {
     #[inline]
     pub fn kind(&self) -> ErrorKind {
         match self {
-            Self::UnexpectedEof { .. } => ErrorKind::UnexpectedEof,
+            Self::MalformedIndex => ErrorKind::InvalidData,
+            Self::IndexMismatch { .. } => ErrorKind::InvalidData,
+            Self::OverlappingEntry => ErrorKind::Unsupported,
+            Self::Poisoned(cause) => cause.kind(),
         }
     }
 }
 
-impl std::error::Error for ReadError {}
+impl std::error::Error for ReadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Poisoned(cause) => Some(cause),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for ReadError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::UnexpectedEof { expected, received } => format!(
-                "expecting more data for entry; expected = {expected}, received = {received}"
-            )
-            .fmt(f),
+            Self::MalformedIndex => "malformed archive index".fmt(f),
+            Self::IndexMismatch { offset } => {
+                format!("index does not match archive; offset = {offset}").fmt(f)
+            }
+            Self::OverlappingEntry => "cannot read next entry while another is being read".fmt(f),
+            Self::Poisoned(cause) => {
+                format!("archive is unusable after an earlier error: {cause}").fmt(f)
+            }
         }
     }
 }
@@ -49,3 +66,199 @@ impl<T> From<ReadError> for Poll<Result<T>> {
         Poll::Ready(Err(value.into()))
     }
 }
+
+/// An error in the archive being read, with where it was found.
+///
+/// This converts into an [IoError] of the matching kind, which is how it's
+/// returned. Get it back with [IoError::get_ref] and downcasting.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub enum ArchiveError {
+    /// The checksum of a header doesn't match its bytes.
+    InvalidChecksum {
+        expected: u32,
+        actual: u32,
+        at: ErrorLocation,
+    },
+    /// A numeric header field is malformed.
+    InvalidField {
+        field: HeaderField,
+        at: ErrorLocation,
+    },
+    /// The archive ended in the middle of something.
+    UnexpectedEof { phase: ReadPhase, at: ErrorLocation },
+    /// The block after the first empty block of the end-of-archive marker
+    /// is not empty.
+    NonZeroTrailer { at: ErrorLocation },
+    /// An entry has a type that cannot be read.
+    UnsupportedType { typeflag: u8, at: ErrorLocation },
+    /// A PAX extended header record is malformed, or its value is invalid
+    /// for its key. `key` is missing if the record couldn't be parsed.
+    InvalidExtension {
+        key: Option<String>,
+        at: ErrorLocation,
+    },
+    /// The sparse map of an entry is malformed or doesn't match its data.
+    InvalidSparseMap { at: ErrorLocation },
+    /// The data of an extension entry, or a sparse map, exceeds the
+    /// [maximum size][crate::Archive::set_max_extension_size].
+    ExtensionTooLarge {
+        size: u64,
+        max: u64,
+        at: ErrorLocation,
+    },
+}
+
+/// Where in the archive an [ArchiveError] was found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorLocation {
+    /// The index of the entry, counting from zero. Errors in headers are
+    /// those of the entry the headers are for, and errors at the end of
+    /// the archive are those of the entry that would have followed.
+    pub entry: u64,
+    /// The path of the entry, if known.
+    pub path: Option<Vec<u8>>,
+    /// The offset in the archive of the header of the entry, or of where
+    /// the archive ended or the error was found.
+    pub offset: u64,
+}
+
+/// A numeric header field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum HeaderField {
+    Uid,
+    Gid,
+    Size,
+    Mtime,
+    Checksum,
+    Atime,
+    Ctime,
+}
+
+/// What was being read when the archive ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadPhase {
+    /// A header, or the end-of-archive marker in its place.
+    Header,
+    /// Entry data.
+    Data,
+    /// The padding after entry data.
+    Alignment,
+    /// The second block of the end-of-archive marker.
+    Trailer,
+}
+
+impl ArchiveError {
+    #[inline]
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::InvalidChecksum { .. } => ErrorKind::InvalidData,
+            Self::InvalidField { .. } => ErrorKind::InvalidData,
+            Self::UnexpectedEof { .. } => ErrorKind::UnexpectedEof,
+            Self::NonZeroTrailer { .. } => ErrorKind::InvalidData,
+            Self::UnsupportedType { .. } => ErrorKind::Unsupported,
+            Self::InvalidExtension { .. } => ErrorKind::InvalidData,
+            Self::InvalidSparseMap { .. } => ErrorKind::InvalidData,
+            Self::ExtensionTooLarge { .. } => ErrorKind::InvalidData,
+        }
+    }
+
+    /// Returns where the error was found.
+    pub fn location(&self) -> &ErrorLocation {
+        match self {
+            Self::InvalidChecksum { at, .. }
+            | Self::InvalidField { at, .. }
+            | Self::UnexpectedEof { at, .. }
+            | Self::NonZeroTrailer { at }
+            | Self::UnsupportedType { at, .. }
+            | Self::InvalidExtension { at, .. }
+            | Self::InvalidSparseMap { at }
+            | Self::ExtensionTooLarge { at, .. } => at,
+        }
+    }
+
+    fn location_mut(&mut self) -> &mut ErrorLocation {
+        match self {
+            Self::InvalidChecksum { at, .. }
+            | Self::InvalidField { at, .. }
+            | Self::UnexpectedEof { at, .. }
+            | Self::NonZeroTrailer { at }
+            | Self::UnsupportedType { at, .. }
+            | Self::InvalidExtension { at, .. }
+            | Self::InvalidSparseMap { at }
+            | Self::ExtensionTooLarge { at, .. } => at,
+        }
+    }
+}
+
+/// Fills in the path of the entry at `index` in `err`, if it's an
+/// [ArchiveError] about that entry without one.
+pub(crate) fn with_path(mut err: IoError, index: u64, path: impl FnOnce() -> Vec<u8>) -> IoError {
+    let at = err
+        .get_mut()
+        .and_then(|err| err.downcast_mut::<ArchiveError>())
+        .map(ArchiveError::location_mut);
+    if let Some(at) = at.filter(|at| at.entry == index && at.path.is_none()) {
+        at.path = Some(path());
+    }
+    err
+}
+
+impl std::error::Error for ArchiveError {}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidChecksum {
+                expected, actual, ..
+            } => write!(
+                f,
+                "invalid header checksum; expected = {expected}, actual = {actual}"
+            )?,
+            Self::InvalidField { field, .. } => write!(f, "invalid numeric field: {field:?}")?,
+            Self::UnexpectedEof { phase, .. } => {
+                write!(f, "unexpected end of archive; phase = {phase:?}")?
+            }
+            Self::NonZeroTrailer { .. } => "expecting empty block".fmt(f)?,
+            Self::UnsupportedType { typeflag, .. } => {
+                write!(f, "unsupported entry type: {:?}", char::from(*typeflag))?
+            }
+            Self::InvalidExtension { key: Some(key), .. } => {
+                write!(f, "invalid value for pax attribute {key}")?
+            }
+            Self::InvalidExtension { key: None, .. } => "malformed pax extended header".fmt(f)?,
+            Self::InvalidSparseMap { .. } => "malformed sparse map".fmt(f)?,
+            Self::ExtensionTooLarge { size, max, .. } => {
+                write!(f, "extension entry too large; size = {size}, max = {max}")?
+            }
+        }
+        let at = self.location();
+        write!(f, "; entry = {}, offset = {}", at.entry, at.offset)?;
+        if let Some(path) = &at.path {
+            write!(f, ", path = {}", String::from_utf8_lossy(path))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ArchiveError> for IoError {
+    #[inline]
+    fn from(value: ArchiveError) -> Self {
+        IoError::new(value.kind(), value)
+    }
+}
+
+impl<T> From<ArchiveError> for Result<T> {
+    #[inline]
+    fn from(value: ArchiveError) -> Self {
+        Err(value.into())
+    }
+}
+
+impl<T> From<ArchiveError> for Poll<Result<T>> {
+    #[inline]
+    fn from(value: ArchiveError) -> Self {
+        Poll::Ready(Err(value.into()))
+    }
+}
@@ -0,0 +1,277 @@
+use std::io::{Error as IoError, Result};
+use std::mem;
+
+use crate::shared::block::{BLOCK_SIZE, Header};
+use crate::shared::ext::EntryExtensions;
+use crate::shared::pax::PaxAttributes;
+use crate::shared::sparse::{Sparse, SparseRegion};
+
+use super::{ArchiveError, ErrorLocation};
+
+/// The default maximum size of extension entry data. See [Extensions::set_max_size].
+const DEFAULT_MAX_SIZE: u64 = 1024 * 1024;
+
+/// Data being received ahead of the next regular entry.
+#[derive(Debug)]
+enum Receiving {
+    /// An extension entry and its data so far.
+    Extension(Header, Vec<u8>),
+
+    /// The sparse map at the start of the data of a sparse entry, with the
+    /// entry header, the map data so far and the length of the entry.
+    SparseMap(Header, Vec<u8>, u64),
+}
+
+/// Tracks extension entries, such as PAX extended headers or GNU long names,
+/// that precede and apply to the next regular entry in the archive.
+#[derive(Debug)]
+pub struct Extensions {
+    /// The data currently being received.
+    receiving: Option<Receiving>,
+
+    /// Extensions for the next regular entry.
+    next: EntryExtensions,
+
+    /// The regions of a PAX 0.0 sparse map for the next regular entry.
+    /// These are repeated records that [PaxAttributes] cannot represent.
+    sparse_regions: Vec<SparseRegion>,
+
+    /// Attributes for all subsequent entries, until overridden.
+    global: PaxAttributes,
+
+    /// The maximum data size of an extension entry.
+    max_size: u64,
+}
+
+impl Default for Extensions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            receiving: None,
+            next: EntryExtensions::default(),
+            sparse_regions: Vec::new(),
+            global: PaxAttributes::default(),
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+}
+
+impl Extensions {
+    /// Returns whether the given header belongs to an extension entry.
+    #[inline]
+    pub fn is_extension(header: &Header) -> bool {
+        let kind = header.entry_type();
+        kind.is_pax_local_extensions()
+            || kind.is_pax_global_extensions()
+            || kind.is_gnu_longname()
+            || kind.is_gnu_longlink()
+    }
+
+    /// Returns the global attributes currently in effect.
+    #[inline]
+    pub fn global(&self) -> &PaxAttributes {
+        &self.global
+    }
+
+    /// Sets the maximum data size of an extension entry. Extension entries
+    /// are buffered in memory, so this guards against malformed or malicious
+    /// archives.
+    #[inline]
+    pub fn set_max_size(&mut self, max: u64) {
+        self.max_size = max;
+    }
+
+    /// Drops the extensions received so far for the next regular entry,
+    /// leaving only the global ones behind.
+    pub fn reset(&mut self) {
+        self.receiving = None;
+        self.next = EntryExtensions::default();
+        self.sparse_regions.clear();
+    }
+
+    /// Returns the data length of the entry whose data is being received,
+    /// if any.
+    #[inline]
+    pub fn receiving_len(&self) -> Option<u64> {
+        match self.receiving.as_ref()? {
+            // This cannot fail because we'd have already errored in [Self::start].
+            Receiving::Extension(header, _) => Some(header.entry_size().unwrap()),
+            Receiving::SparseMap(_, _, len) => Some(*len),
+        }
+    }
+
+    /// Starts receiving the data of the extension entry with the given
+    /// header. Errors are reported `at` the entry the extension is for.
+    pub fn start(&mut self, header: Header, at: &ErrorLocation) -> Result<()> {
+        debug_assert!(self.receiving.is_none());
+        let len = header.entry_size()?;
+        if len > self.max_size {
+            return ArchiveError::ExtensionTooLarge {
+                size: len,
+                max: self.max_size,
+                at: at.clone(),
+            }
+            .into();
+        }
+        let data = Vec::with_capacity(len as usize);
+        self.receiving = Some(Receiving::Extension(header, data));
+        Ok(())
+    }
+
+    /// Prepares for the regular entry with the given header. If it's a
+    /// sparse entry whose map precedes its data, starts receiving the map
+    /// and returns the length of the entry.
+    pub fn start_entry(&mut self, header: &Header, at: &ErrorLocation) -> Result<Option<u64>> {
+        debug_assert!(self.receiving.is_none());
+        let sparse = match Sparse::from_gnu(header).map_err(|_| invalid_map(at))? {
+            Some(sparse) => Some(sparse),
+            None => Sparse::from_pax(&self.next.pax, mem::take(&mut self.sparse_regions))
+                .map_err(|_| invalid_map(at))?,
+        };
+        let Some((sparse, in_data)) = sparse else {
+            return Ok(None);
+        };
+        self.next.sparse = Some(sparse);
+        if !in_data {
+            return Ok(None);
+        }
+
+        let len = if header.entry_type().is_gnu_sparse() {
+            // Old GNU extension blocks follow the header but are not counted
+            // in the entry size. We find out how many there are as we go.
+            header.entry_size()? + BLOCK_SIZE as u64
+        } else {
+            let size = self.next.pax.size();
+            size.map_err(|_| invalid_value("size", at))?
+                .map_or_else(|| header.entry_size(), Ok)?
+        };
+        let header = header.clone();
+        self.receiving = Some(Receiving::SparseMap(header, Vec::new(), len));
+        Ok(Some(len))
+    }
+
+    /// Receives data for the entry being received. Returns how much of the
+    /// data was used and whether the next regular entry is ready, which is
+    /// when the sparse map preceding its data is complete.
+    ///
+    /// The length returned by [Self::receiving_len] may grow as a result.
+    pub fn receive(&mut self, buf: &[u8], at: &ErrorLocation) -> Result<(usize, bool)> {
+        let receiving = self.receiving.as_mut().expect("not receiving data");
+        let (header, data, len) = match receiving {
+            Receiving::Extension(_, data) => {
+                data.extend_from_slice(buf);
+                return Ok((buf.len(), false));
+            }
+            Receiving::SparseMap(header, data, len) => (header, data, len),
+        };
+
+        // Sparse maps are made of whole blocks, and we must not consume any
+        // data past them.
+        let amt = buf.len().min(BLOCK_SIZE - data.len() % BLOCK_SIZE);
+        data.extend_from_slice(&buf[..amt]);
+        if data.len() % BLOCK_SIZE != 0 {
+            return Ok((amt, false));
+        }
+        if data.len() as u64 > self.max_size {
+            return ArchiveError::ExtensionTooLarge {
+                size: data.len() as u64,
+                max: self.max_size,
+                at: at.clone(),
+            }
+            .into();
+        }
+
+        let sparse = self.next.sparse.as_mut().expect("sparse entry");
+        let done = if header.entry_type().is_gnu_sparse() {
+            let more = sparse
+                .extend_from_gnu_block(&data[data.len() - BLOCK_SIZE..])
+                .map_err(|_| invalid_map(at))?;
+            if more {
+                *len += BLOCK_SIZE as u64;
+            }
+            !more
+        } else {
+            sparse
+                .extend_from_pax_map(data)
+                .map_err(|_| invalid_map(at))?
+        };
+        Ok((amt, done))
+    }
+
+    /// Completes the extension entry being received and applies its data.
+    pub fn finish(&mut self, at: &ErrorLocation) -> Result<()> {
+        let receiving = self.receiving.take().expect("not receiving data");
+        let Receiving::Extension(header, data) = receiving else {
+            // The entry data ended before its sparse map did.
+            return Err(invalid_map(at));
+        };
+        let kind = header.entry_type();
+        if kind.is_pax_global_extensions() {
+            let res = self.global.extend_from_bytes(&data);
+            res.map_err(|_| invalid_record(at))
+        } else if kind.is_pax_local_extensions() {
+            self.next.local_pax = true;
+            let res = self.next.pax.extend_from_bytes(&data);
+            res.map_err(|_| invalid_record(at))?;
+            let regions = Sparse::pax_regions(&data).map_err(|_| invalid_map(at))?;
+            self.sparse_regions.extend(regions);
+            Ok(())
+        } else if kind.is_gnu_longname() {
+            self.next.long_name = Some(trim_nul(data));
+            Ok(())
+        } else {
+            debug_assert!(kind.is_gnu_longlink());
+            self.next.long_link = Some(trim_nul(data));
+            Ok(())
+        }
+    }
+
+    /// Takes the extensions that apply to the next regular entry, leaving
+    /// only the global ones behind. Entry attributes take precedence over
+    /// global ones.
+    pub fn take(&mut self) -> EntryExtensions {
+        self.sparse_regions.clear();
+        let mut next = mem::take(&mut self.next);
+        if !self.global.is_empty() {
+            let mut merged = self.global.clone();
+            merged.merge(&next.pax);
+            next.pax = merged;
+        }
+        next
+    }
+
+    /// Takes the header of the sparse entry whose map has been received, and
+    /// the extensions that apply to it.
+    pub fn take_entry(&mut self) -> (Header, EntryExtensions) {
+        let Some(Receiving::SparseMap(header, _, _)) = self.receiving.take() else {
+            panic!("not receiving sparse map");
+        };
+        (header, self.take())
+    }
+}
+
+/// Returns the error for a PAX extended header that can't be parsed.
+fn invalid_record(at: &ErrorLocation) -> IoError {
+    let at = at.clone();
+    ArchiveError::InvalidExtension { key: None, at }.into()
+}
+
+/// Returns the error for an invalid value of the PAX attribute `key`.
+pub(crate) fn invalid_value(key: &str, at: &ErrorLocation) -> IoError {
+    let key = Some(key.to_owned());
+    let at = at.clone();
+    ArchiveError::InvalidExtension { key, at }.into()
+}
+
+/// Returns the error for a malformed sparse map.
+pub(crate) fn invalid_map(at: &ErrorLocation) -> IoError {
+    let at = at.clone();
+    ArchiveError::InvalidSparseMap { at }.into()
+}
+
+/// GNU long names are NUL-terminated, and may be padded further.
+fn trim_nul(mut data: Vec<u8>) -> Box<[u8]> {
+    let len = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+    data.truncate(len);
+    data.into_boxed_slice()
+}
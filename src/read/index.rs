@@ -0,0 +1,175 @@
+use std::future::poll_fn;
+use std::io::Result;
+use std::pin::Pin;
+
+use tokio::io::{AsyncRead, AsyncSeek};
+
+use crate::{Archive, Entry, EntryType};
+
+use super::ReadError;
+
+/// The bytes an encoded index starts with, ending with the format version.
+const MAGIC: &[u8; 8] = b"tarioix\x01";
+
+/// Where an entry is in an archive, as recorded by [Archive::index].
+///
+/// Offsets are in bytes from the start of the archive, which is where the
+/// source was positioned when the archive was created.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexEntry {
+    /// The path of the entry, as returned by [Entry::path].
+    pub path: Vec<u8>,
+    /// The index of the entry in the archive, counting from zero, as in
+    /// [ErrorLocation::entry][crate::ErrorLocation::entry].
+    pub index: u64,
+    /// The offset of the first header of the entry, which is that of any
+    /// extension entries preceding it.
+    pub header_offset: u64,
+    /// The offset of the entry data. For sparse entries, this is the offset
+    /// of the first data region.
+    pub data_offset: u64,
+    /// The file size of the entry, as returned by [Entry::size].
+    pub size: u64,
+    /// The type of the entry.
+    pub kind: EntryType,
+}
+
+impl IndexEntry {
+    fn new<R>(entry: &Entry<'_, R>) -> Self {
+        Self {
+            path: entry.path().into_owned(),
+            index: entry.index,
+            header_offset: entry.header_offset,
+            data_offset: entry.data_offset,
+            size: entry.size(),
+            kind: entry.header().entry_type(),
+        }
+    }
+
+    /// Encodes the given index into a compact binary form, to be stored
+    /// alongside the archive and read back with [Self::decode].
+    ///
+    /// This fails with [ReadError::MalformedIndex] if the data of an entry
+    /// is before its header.
+    pub fn encode(index: &[Self]) -> Result<Vec<u8>> {
+        let mut buf = MAGIC.to_vec();
+        put_varint(&mut buf, index.len() as u64);
+        for entry in index {
+            put_varint(&mut buf, entry.path.len() as u64);
+            buf.extend_from_slice(&entry.path);
+            put_varint(&mut buf, entry.index);
+            put_varint(&mut buf, entry.header_offset);
+            let data_offset = entry.data_offset.checked_sub(entry.header_offset);
+            put_varint(&mut buf, data_offset.ok_or(ReadError::MalformedIndex)?);
+            put_varint(&mut buf, entry.size);
+            buf.push(entry.kind.as_byte());
+        }
+        Ok(buf)
+    }
+
+    /// Decodes an index encoded with [Self::encode].
+    pub fn decode(mut buf: &[u8]) -> Result<Vec<Self>> {
+        let Some(rest) = buf.strip_prefix(MAGIC) else {
+            return ReadError::MalformedIndex.into();
+        };
+        buf = rest;
+
+        let count = get_varint(&mut buf)?;
+        // Every entry takes at least 6 bytes, which bounds the allocation.
+        let mut index = Vec::with_capacity(count.min(buf.len() as u64 / 6) as usize);
+        for _ in 0..count {
+            let len = get_varint(&mut buf)?;
+            let path = take(&mut buf, len)?.to_vec();
+            let entry = get_varint(&mut buf)?;
+            let header_offset = get_varint(&mut buf)?;
+            let data_offset = header_offset
+                .checked_add(get_varint(&mut buf)?)
+                .ok_or(ReadError::MalformedIndex)?;
+            let size = get_varint(&mut buf)?;
+            let kind = EntryType::new(take(&mut buf, 1)?[0]);
+            index.push(Self {
+                path,
+                index: entry,
+                header_offset,
+                data_offset,
+                size,
+                kind,
+            });
+        }
+
+        if !buf.is_empty() {
+            return ReadError::MalformedIndex.into();
+        }
+        Ok(index)
+    }
+}
+
+/// Appends `value` as an unsigned LEB128 number.
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Takes an unsigned LEB128 number off the front of `buf`.
+fn get_varint(buf: &mut &[u8]) -> Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = take(buf, 1)?[0];
+        let bits = u64::from(byte & 0x7f);
+        if bits << shift >> shift != bits {
+            break;
+        }
+        value |= bits << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    ReadError::MalformedIndex.into()
+}
+
+/// Takes `len` bytes off the front of `buf`.
+fn take<'a>(buf: &mut &'a [u8], len: u64) -> Result<&'a [u8]> {
+    let len = usize::try_from(len).map_err(|_| ReadError::MalformedIndex)?;
+    if len > buf.len() {
+        return ReadError::MalformedIndex.into();
+    }
+    let (head, tail) = buf.split_at(len);
+    *buf = tail;
+    Ok(head)
+}
+
+pub(crate) async fn index_archive<R>(archive: &mut Archive<R>) -> Result<Vec<IndexEntry>>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    archive.seek.enable();
+    let mut index = Vec::new();
+    while let Some(mut entry) = archive.next_entry().await? {
+        index.push(IndexEntry::new(&entry));
+        poll_fn(|cx| Pin::new(&mut entry).poll_skip(cx, true)).await?;
+    }
+    Ok(index)
+}
+
+pub(crate) async fn open_entry<'a, R>(
+    archive: &'a mut Archive<R>,
+    entry: &IndexEntry,
+) -> Result<Entry<'a, R>>
+where
+    R: AsyncRead + AsyncSeek + Unpin,
+{
+    archive.seek.enable();
+    Pin::new(&mut *archive).start_seek_header(entry.header_offset, entry.index)?;
+    match archive.next_entry().await? {
+        Some(found) if found.data_offset == entry.data_offset && *found.path() == *entry.path => {
+            Ok(found)
+        }
+        _ => ReadError::IndexMismatch {
+            offset: entry.header_offset,
+        }
+        .into(),
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::{Error as IoError, ErrorKind, IoSlice, Result};
+use std::io::{Error as IoError, IoSlice, Result};
 use std::mem;
 use std::pin::Pin;
 use std::task::{Context, Poll, ready};
@@ -7,15 +7,117 @@ use std::task::{Context, Poll, ready};
 use futures_core::Stream;
 use tokio::io::{AsyncBufRead, AsyncRead, ReadBuf};
 
-use crate::shared::block::{Block, Header};
+use crate::shared::block::Block;
 use crate::shared::buffer::ReadableRegion;
+use crate::shared::ext::EntryExtensions;
 use crate::shared::slices::IntoBuffersIterator;
+use crate::shared::sparse::{Chunk, Sparse};
 use crate::shared::state::State;
 
-use crate::{Archive, BLOCK_SIZE, Entry, TRACING_ENABLED};
+use crate::{Archive, BLOCK_SIZE, Entry, Header, TRACING_ENABLED, entry_path};
+
+#[cfg(feature = "compression")]
+mod decode;
+#[cfg(feature = "compression")]
+pub use self::decode::Decoder;
 
 mod error;
-pub use self::error::ReadError;
+pub(crate) use self::error::with_path;
+pub use self::error::{ArchiveError, ErrorLocation, HeaderField, ReadError, ReadPhase};
+
+mod ext;
+pub(crate) use self::ext::Extensions;
+
+mod index;
+pub use self::index::IndexEntry;
+pub(crate) use self::index::{index_archive, open_entry};
+
+mod resync;
+pub use self::resync::DamagedRegion;
+pub(crate) use self::resync::Resync;
+
+mod seek;
+pub(crate) use self::seek::Seeker;
+
+#[cfg(feature = "fs")]
+mod unpack;
+#[cfg(feature = "fs")]
+pub use self::unpack::{PathPolicy, UnpackError, UnpackSummary, Unpacked};
+#[cfg(feature = "fs")]
+pub(crate) use self::unpack::{UnpackOptions, unpack_archive, unpack_in};
+#[cfg(not(feature = "fs"))]
+pub(crate) type UnpackOptions = ();
+
+impl<T> Archive<T> {
+    /// Returns where in the archive `offset` is, for errors found there.
+    fn location(&self, offset: u64) -> ErrorLocation {
+        // Entry data belongs to the last entry handed out, unless it is that
+        // of extension entries, which belong to the next one with headers.
+        let current = self.header_pos.is_none()
+            && matches!(
+                self.state,
+                State::ReceivingData(_) | State::ReceivedData | State::AligningData(_)
+            );
+        ErrorLocation {
+            entry: match current {
+                true => self.entries.saturating_sub(1),
+                false => self.entries,
+            },
+            path: None,
+            offset,
+        }
+    }
+
+    /// Returns where the entry whose headers are being read is, for errors
+    /// in them or in the extension entries preceding them.
+    fn entry_location(&self) -> ErrorLocation {
+        self.location(self.header_pos.unwrap_or(self.pos))
+    }
+
+    /// Returns the file size of the entry with the given header and
+    /// extensions, and the number of bytes it occupies in the archive.
+    pub(crate) fn entry_lens(
+        &self,
+        header: &Header,
+        ext: &EntryExtensions,
+    ) -> std::result::Result<(u64, u64), ArchiveError> {
+        let at = || ErrorLocation {
+            path: Some(entry_path(header, ext).into_owned()),
+            ..self.entry_location()
+        };
+
+        // A PAX size overrides the one in the header, which may not be able
+        // to represent it.
+        let size = ext.pax.size().map_err(|_| ArchiveError::InvalidExtension {
+            key: Some("size".to_owned()),
+            at: at(),
+        })?;
+        let (mut size, mut len) = match size.map(|size| (size, size)) {
+            Some(lens) => lens,
+            None => header
+                .size()
+                .and_then(|size| Ok((size, header.entry_size()?)))
+                .map_err(|_| ArchiveError::InvalidField {
+                    field: HeaderField::Size,
+                    at: at(),
+                })?,
+        };
+
+        // Sparse entries only store their data regions, preceded by the
+        // sparse map. Old GNU sparse headers don't count the extension
+        // blocks of the map in the entry size.
+        if let Some(sparse) = &ext.sparse {
+            if header.entry_type().is_gnu_sparse() {
+                len += sparse.map_len;
+            }
+            sparse
+                .validate(len - sparse.map_len)
+                .map_err(|_| ArchiveError::InvalidSparseMap { at: at() })?;
+            size = sparse.size;
+        }
+        Ok((size, len))
+    }
+}
 
 impl<R: AsyncRead> Archive<R> {
     /// Reads from the source object and fills the internal buffer, until one
@@ -24,23 +126,100 @@ impl<R: AsyncRead> Archive<R> {
     fn poll_next_state(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-        header: Option<&Header>,
+        len: Option<u64>,
     ) -> Poll<Result<(State, usize)>> {
         ready!(self.as_mut().poll_fill_buf(cx))?;
 
         let this = self.as_mut().project();
+        if *this.state == State::ReceivedEof {
+            // The source ended where another archive could have started.
+            return Poll::Ready(Ok((State::ReceivedEof, 0)));
+        }
         let buf = this.buf.buffered_bytes();
-        Poll::Ready(this.state.next(buf, header))
+        match this.state.next(buf, len) {
+            Err(_) if matches!(*this.state, State::ReceivingEof(_)) => {
+                // The first empty block has been consumed, so we are at the
+                // start of the buffer or past the start of the second one.
+                let State::ReceivingEof(rem) = *this.state else {
+                    unreachable!();
+                };
+                let offset = *this.pos - (BLOCK_SIZE - rem) as u64;
+                let at = self.location(offset);
+                ArchiveError::NonZeroTrailer { at }.into()
+            }
+            res => Poll::Ready(res),
+        }
+    }
+
+    /// Returns the error for the source ending before the archive does.
+    fn eof_error(&self) -> IoError {
+        let phase = match self.state {
+            State::ReceivedHeader | State::ReceivingData(_) | State::ReceivedData => {
+                ReadPhase::Data
+            }
+            State::AligningData(_) => ReadPhase::Alignment,
+            State::ReceivingEof(_) => ReadPhase::Trailer,
+            _ => ReadPhase::Header,
+        };
+        let offset = self.pos + self.buf.buffered_bytes().len() as u64;
+        let at = self.location(offset);
+        ArchiveError::UnexpectedEof { phase, at }.into()
+    }
+
+    /// Parses the header block at the start of the buffer, checking the
+    /// fields needed to read the entry.
+    fn parse_header(&self) -> std::result::Result<Header, ArchiveError> {
+        let block = Block::from_bytes(&self.buf.buffered_bytes()[..BLOCK_SIZE]);
+        let at = |path: Option<&Header>| ErrorLocation {
+            path: path.map(|header| header.path_bytes().into_owned()),
+            ..self.location(self.pos)
+        };
+
+        let Ok((expected, actual)) = block.checksums() else {
+            let field = HeaderField::Checksum;
+            return Err(ArchiveError::InvalidField {
+                field,
+                at: at(None),
+            });
+        };
+        let Ok(header) = block.as_header() else {
+            let at = at(None);
+            return Err(ArchiveError::InvalidChecksum {
+                expected,
+                actual,
+                at,
+            });
+        };
+        let header = header.to_owned();
+
+        if header.entry_size().is_err() {
+            let field = HeaderField::Size;
+            let at = at(Some(&header));
+            return Err(ArchiveError::InvalidField { field, at });
+        }
+
+        // GNU multi-volume continuations hold the rest of a file from the
+        // previous volume, which makes no sense on its own.
+        let typeflag = header.entry_type().as_byte();
+        if typeflag == b'M' {
+            let at = at(Some(&header));
+            return Err(ArchiveError::UnsupportedType { typeflag, at });
+        }
+
+        Ok(header)
     }
 
     /// Reads from the source object until the next entry header is received
-    /// or EOF is reached.
+    /// or EOF is reached. Extension entries preceding the entry are consumed
+    /// and applied to it.
     ///
-    /// This will panic if called while an entry is being read.
+    /// This fails with [ReadError::OverlappingEntry] if called while an
+    /// entry is being read, leaving the archive poisoned.
     fn poll_next_entry(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Result<Option<Entry<'_, R>>>> {
+        self.check_poison()?;
         if self.state.is_terminal() {
             return Poll::Ready(Ok(None));
         }
@@ -50,37 +229,118 @@ impl<R: AsyncRead> Archive<R> {
                 eprintln!("     |entry: {:?}", self.state);
             }
 
+            if self.resync.is_scanning() {
+                if !ready!(self.as_mut().poll_resync(cx))? {
+                    return Poll::Ready(Ok(None));
+                }
+                continue;
+            }
+
+            if let Some(len) = self.ext.receiving_len() {
+                let buf = ready!(self.as_mut().poll_read_entry(cx, len))?;
+                let amt = buf.len();
+                let at = self.entry_location();
+                let this = self.as_mut().project();
+                if amt == 0 {
+                    this.ext.finish(&at)?;
+                    continue;
+                }
+
+                let (amt, ready) = this.ext.receive(&this.buf.buffered_bytes()[..amt], &at)?;
+                let new_len = this.ext.receiving_len().unwrap();
+                if new_len > len {
+                    // More sparse map blocks to receive before the data.
+                    this.state.extend_data(new_len - len);
+                }
+                self.as_mut().consume(amt, Some(new_len));
+
+                if ready {
+                    let (header, ext) = self.as_mut().project().ext.take_entry();
+                    let mut entry = Entry::new(self, header, ext)?;
+                    entry.set_read_offsets();
+                    return Poll::Ready(Ok(Some(entry)));
+                }
+                continue;
+            }
+
             let (state, amt) = ready!(self.as_mut().poll_next_state(cx, None))?;
 
             match state {
                 State::ReceivedHeader => {
+                    let header = match self.parse_header() {
+                        Ok(header) => header,
+                        Err(err) if self.resync.is_enabled() => {
+                            self.as_mut().start_resync(err);
+                            continue;
+                        }
+                        Err(err) => return err.into(),
+                    };
+                    // Extension headers are part of the entry they apply to.
+                    let pos = self.pos;
+                    self.as_mut().project().header_pos.get_or_insert(pos);
+                    let at = self.entry_location();
                     let this = self.as_mut().project();
-                    let buf = this.buf.buffered_bytes();
-                    let block = Block::from_bytes(&buf[..BLOCK_SIZE]);
-                    let header = block.as_header()?.to_owned();
-                    self.as_mut().consume(amt, Some(&header));
-                    let entry = Entry::new(self, header)?;
+
+                    if Extensions::is_extension(&header) {
+                        let len = header.entry_size()?;
+                        this.ext.start(header, &at)?;
+                        self.as_mut().consume(amt, Some(len));
+                        continue;
+                    }
+
+                    if let Some(len) = this.ext.start_entry(&header, &at)? {
+                        // The entry data starts with a sparse map, which we
+                        // need before handing out the entry.
+                        self.as_mut().consume(amt, Some(len));
+                        continue;
+                    }
+
+                    let ext = this.ext.take();
+                    let mut entry = Entry::new(self, header, ext)?;
+                    let len = entry.len();
+                    entry.archive.as_mut().consume(amt, Some(len));
+                    if len == 0 {
+                        entry.archive.as_mut().consume_empty_entry();
+                    }
+                    entry.set_read_offsets();
                     return Poll::Ready(Ok(Some(entry)));
                 }
 
                 State::ReceivedEof => {
-                    self.consume(amt, None);
+                    if self.state != State::ReceivedEof {
+                        self.as_mut().consume(amt, None);
+                        *self.project().trailer_complete = true;
+                    }
                     return Poll::Ready(Ok(None));
                 }
 
-                State::ReceivingHeader(_, _) | State::ReceivingEof(_) => {
+                State::ReceivingEof(_) if self.ignore_zeros => {
+                    // Skip the empty block as if it were never there.
+                    self.as_mut().consume(amt, None);
+                    *self.as_mut().project().state = State::ExpectingHeader;
+                    continue;
+                }
+
+                State::ReceivingHeader(BLOCK_SIZE, _) | State::ReceivingEof(_) => {
                     self.as_mut().consume(amt, None);
                     continue;
                 }
 
+                State::ReceivingHeader(_, _) => {
+                    // Only part of the header is buffered. Keep it, since
+                    // the header is parsed from the buffer in one piece.
+                    ready!(self.as_mut().poll_fill_block(cx))?;
+                    continue;
+                }
+
                 State::AligningData(_) | State::AlignedData => {
                     // Finishing off a previous entry.
                     self.as_mut().consume(amt, None);
                     continue;
                 }
 
-                s => {
-                    panic!("cannot read next entry while another entry is being read ({s:?})");
+                _ => {
+                    return Poll::Ready(Err(self.poison(ReadError::OverlappingEntry)));
                 }
             }
         }
@@ -88,22 +348,32 @@ impl<R: AsyncRead> Archive<R> {
 
     /// Reads from the source object and returns buffers of entry data until
     /// all entry data is consumed. It is necessary to call [Self::consume]
-    /// afterwards in order to get buffers with new data.
-    ///
-    /// This will panic if called while no entry is being read.
+    /// afterwards in order to get buffers with new data. Once all of the
+    /// entry is consumed, this keeps returning an empty buffer.
     fn poll_read_entry(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-        header: &Header,
+        len: u64,
     ) -> Poll<Result<&[u8]>> {
+        if self.state == State::ExpectingHeader {
+            // All of the entry has been consumed.
+            return Poll::Ready(Ok(&[]));
+        }
+
         loop {
             if TRACING_ENABLED {
                 eprintln!("     |read: {:?}", self.state);
             }
 
-            let (state, amt) = ready!(self.as_mut().poll_next_state(cx, Some(header)))?;
+            let (state, amt) = ready!(self.as_mut().poll_next_state(cx, Some(len)))?;
 
             match state {
+                State::ReceivingData(_) | State::ReceivedData if amt == 0 => {
+                    // Entries with no data transition without reading anything.
+                    self.as_mut().consume(amt, Some(len));
+                    continue;
+                }
+
                 State::ReceivingData(_) | State::ReceivedData => {
                     let this = self.project();
                     let buf = this.buf.buffered_bytes();
@@ -117,7 +387,7 @@ impl<R: AsyncRead> Archive<R> {
                 }
 
                 State::AlignedData => {
-                    self.as_mut().consume(amt, Some(header));
+                    self.as_mut().consume(amt, Some(len));
                     return Poll::Ready(Ok(&[]));
                 }
 
@@ -129,26 +399,85 @@ impl<R: AsyncRead> Archive<R> {
         }
     }
 
-    /// Reads from the source object and consumes all remaining entry data.
+    /// Reads from the source object and consumes all remaining entry data,
+    /// or seeks past what is not buffered if `seek` is set.
     fn poll_skip_entry(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-        header: &Header,
+        len: u64,
+        seek: bool,
     ) -> Poll<Result<()>> {
+        if seek && self.as_mut().start_seek_entry(len)? {
+            let this = self.project();
+            return this.seek.poll_complete(this.io, cx);
+        }
+
         loop {
-            let buf = ready!(self.as_mut().poll_read_entry(cx, header))?;
+            let buf = ready!(self.as_mut().poll_read_entry(cx, len))?;
             let amt = buf.len();
             if amt == 0 {
                 assert_eq!(self.state, State::ExpectingHeader);
                 return Poll::Ready(Ok(()));
             }
-            self.as_mut().consume(amt, Some(header));
+            self.as_mut().consume(amt, Some(len));
+        }
+    }
+
+    /// Starts seeking past the rest of the current entry and its alignment,
+    /// if more of it is left than is buffered, in which case the archive
+    /// is left expecting the next header. Returns whether it did.
+    fn start_seek_entry(self: Pin<&mut Self>, len: u64) -> Result<bool> {
+        let this = self.project();
+        let align = len.next_multiple_of(BLOCK_SIZE as u64) - len;
+        let rem = match *this.state {
+            State::ReceivingData(rem) => rem + align,
+            State::ReceivedData => align,
+            State::AligningData(rem) => rem as u64,
+            _ => return Ok(false),
+        };
+
+        let buffered = this.buf.buffered_bytes().len() as u64;
+        if rem <= buffered {
+            return Ok(false);
+        }
+
+        if TRACING_ENABLED {
+            eprintln!("     | seek: {} / {:?}", rem - buffered, *this.state);
+        }
+        this.seek
+            .start(this.io, *this.pos + buffered, *this.pos + rem)?;
+        this.buf.clear();
+        *this.pos += rem;
+        *this.state = State::ExpectingHeader;
+        Ok(true)
+    }
+
+    /// Starts seeking to the header of the entry of the given index, at the
+    /// given offset in the archive, dropping anything buffered, any
+    /// extensions received so far and any damaged region being skipped.
+    pub(crate) fn start_seek_header(self: Pin<&mut Self>, offset: u64, entry: u64) -> Result<()> {
+        let this = self.project();
+        let buffered = this.buf.buffered_bytes().len() as u64;
+        if TRACING_ENABLED {
+            eprintln!("     | seek: @{offset} / {:?}", *this.state);
         }
+        this.seek.start(this.io, *this.pos + buffered, offset)?;
+        this.buf.clear();
+        this.ext.reset();
+        this.resync.reset();
+        *this.pos = offset;
+        *this.header_pos = None;
+        *this.entries = entry;
+        *this.state = State::ExpectingHeader;
+        Ok(())
     }
 
     /// Reads from the source object and fills the internal buffer.
-    fn poll_fill_buf(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
-        let mut this = self.project();
+    fn poll_fill_buf(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let mut this = self.as_mut().project();
+
+        // A skip may have been dropped while seeking.
+        ready!(this.seek.poll_complete(this.io.as_mut(), cx))?;
 
         if this.buf.buffered_bytes().is_empty() {
             // Try to fill our buffer
@@ -163,10 +492,10 @@ impl<R: AsyncRead> Archive<R> {
             // it is and return an error if this was not expected.
             if bytes_read == 0 {
                 assert!(!this.buf.available_bytes_mut().is_empty());
-                if !this.state.is_terminal() {
-                    let err = IoError::from(ErrorKind::UnexpectedEof);
-                    return Poll::Ready(Err(err));
+                if !this.state.is_terminal() && !self.as_mut().end_early() {
+                    return Poll::Ready(Err(self.eof_error()));
                 }
+                return Poll::Ready(Ok(()));
             }
 
             this.buf.available().commit(bytes_read);
@@ -175,9 +504,59 @@ impl<R: AsyncRead> Archive<R> {
         Poll::Ready(Ok(()))
     }
 
+    /// Reads from the source object into the internal buffer after the bytes
+    /// buffered so far, moving them to the start of the buffer first so that
+    /// a whole block fits.
+    fn poll_fill_block(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let mut this = self.as_mut().project();
+        this.buf.compact();
+
+        let mut buf = ReadBuf::new(this.buf.available_bytes_mut());
+        ready!(this.io.as_mut().poll_read(cx, &mut buf))?;
+
+        let bytes_read = buf.filled().len();
+        if bytes_read == 0 && !self.as_mut().end_early() {
+            return Poll::Ready(Err(self.eof_error()));
+        }
+        self.project().buf.available().commit(bytes_read);
+
+        Poll::Ready(Ok(()))
+    }
+
+    /// Ends the archive where the source ended, if that's allowed between
+    /// entries, dropping the part of the end-of-archive marker buffered.
+    /// Returns whether it did.
+    fn end_early(self: Pin<&mut Self>) -> bool {
+        let this = self.project();
+        let buffered = this.buf.buffered_bytes();
+        let empty = buffered.iter().all(|b| *b == 0);
+        let allowed = match *this.state {
+            State::ExpectingHeader | State::ReceivingHeader(BLOCK_SIZE, true) => {
+                (*this.ignore_zeros && buffered.is_empty())
+                    || (*this.allow_missing_trailer && empty)
+            }
+            State::ReceivingEof(_) => *this.allow_missing_trailer && empty,
+            _ => false,
+        };
+        if allowed {
+            *this.pos += buffered.len() as u64;
+            this.buf.clear();
+            *this.state = State::ReceivedEof;
+        }
+        allowed
+    }
+
+    /// Moves past the end of the entry with no data just received, so that
+    /// it doesn't need to be read to get to the next entry.
+    fn consume_empty_entry(mut self: Pin<&mut Self>) {
+        while self.state != State::ExpectingHeader {
+            self.as_mut().consume(0, Some(0));
+        }
+    }
+
     /// Consumes `amt` from the internal buffer advancing into the archive
     /// and updating the internal state accordingly.
-    fn consume(self: Pin<&mut Self>, amt: usize, header: Option<&Header>) {
+    fn consume(self: Pin<&mut Self>, amt: usize, len: Option<u64>) {
         let this = self.project();
 
         let mut buffered = this.buf.buffered();
@@ -193,7 +572,7 @@ impl<R: AsyncRead> Archive<R> {
         let slices = [IoSlice::new(&buffered.bytes()[..amt])];
         let (state, pos) = this
             .state
-            .take_slices(slices.iter().into_buffers(), header)
+            .take_slices(slices.iter().into_buffers(), len)
             .expect("this slice should have already been checked");
 
         // This is a bit of a catch-all for states we may land but don't care
@@ -202,8 +581,8 @@ impl<R: AsyncRead> Archive<R> {
         // More importantly, this handles the case where our owner consumes
         // the last bits of entry data and we need to transition to reading the
         // entry alignment bytes. We can't do that in `poll_next_entry` because
-        // we don't have a valid header at that point, so we make that transition
-        // here while we have the entry header (since we're being called from
+        // we don't know the entry length at that point, so we make that
+        // transition here while we have it (since we're being called from
         // [Entry::consume]).
         let state = match state {
             State::ReceivingHeader(0, false)
@@ -213,10 +592,9 @@ impl<R: AsyncRead> Archive<R> {
             | State::AligningData(0)
             | State::AlignedData
             | State::ReceivingEof(0) => {
-                // This cannot fail because either we don't need the header
-                // to make the transition, or the header has been checked
-                // to be valid already.
-                state.next(&[], header).unwrap().0
+                // This cannot fail because either we don't need the entry
+                // length to make the transition, or it has been given.
+                state.next(&[], len).unwrap().0
             }
             _ => state,
         };
@@ -234,6 +612,7 @@ impl<R: AsyncRead> Archive<R> {
 
         // Advance our read pointer
         buffered.commit(amt);
+        *this.pos += amt as u64;
 
         // Reset our buffer if we've read it all to make as much room
         // as possible for further data.
@@ -266,8 +645,31 @@ impl<R: AsyncRead> AsyncBufRead for Entry<'_, R> {
             eprintln!(" fill: '{}', size = {}", self.path_lossy(), self.size());
         }
         let this = self.project();
-        let header = this.header;
-        this.archive.as_mut().poll_read_entry(cx, header)
+        let len = *this.len;
+
+        // Sparse files are read as they'd be on disk, with holes filled in.
+        let res = match this.ext.sparse.as_ref().map(Sparse::chunk) {
+            None | Some(Chunk::End) => this.archive.as_mut().poll_read_entry(cx, len),
+            Some(Chunk::Hole(n)) => Poll::Ready(Ok(Sparse::zeros(n))),
+            Some(Chunk::Data(n)) => match this.archive.as_mut().poll_read_entry(cx, len) {
+                Poll::Ready(Ok(buf)) => {
+                    // This cannot be empty since the sparse map adds up to
+                    // the entry data.
+                    debug_assert!(!buf.is_empty());
+                    let amt = buf.len().min(n.try_into().unwrap_or(usize::MAX));
+                    Poll::Ready(Ok(&buf[..amt]))
+                }
+                res => res,
+            },
+        };
+
+        match res {
+            Poll::Ready(Err(err)) => {
+                let path = || entry_path(this.header, this.ext).into_owned();
+                Poll::Ready(Err(with_path(err, *this.index, path)))
+            }
+            res => res,
+        }
     }
 
     fn consume(self: Pin<&mut Self>, amt: usize) {
@@ -275,19 +677,51 @@ impl<R: AsyncRead> AsyncBufRead for Entry<'_, R> {
             eprintln!("consm: '{}', size = {}", self.path_lossy(), self.size());
         }
         let this = self.project();
-        let header = this.header;
-        this.archive.as_mut().consume(amt, Some(header));
+        if amt == 0 && this.archive.state == State::ExpectingHeader {
+            // All of the entry has been consumed.
+            return;
+        }
+        let len = *this.len;
+        match this.ext.sparse.as_mut() {
+            Some(sparse) => {
+                if !matches!(sparse.chunk(), Chunk::Hole(_)) {
+                    this.archive.as_mut().consume(amt, Some(len));
+                }
+                sparse.advance(amt);
+            }
+            None => this.archive.as_mut().consume(amt, Some(len)),
+        }
+    }
+}
+
+impl<R> Entry<'_, R> {
+    /// Records where this entry starts and where its data starts, with its
+    /// headers consumed.
+    fn set_read_offsets(&mut self) {
+        let archive = self.archive.as_mut().project();
+        self.data_offset = *archive.pos;
+        self.header_offset = archive.header_pos.take().unwrap_or(self.data_offset);
+        self.index = *archive.entries;
+        *archive.entries += 1;
     }
 }
 
 impl<R: AsyncRead> Entry<'_, R> {
-    pub(super) fn poll_skip(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+    /// Skips the rest of this entry, seeking past it if `seek` is set, which
+    /// requires seeking to have been enabled.
+    pub(crate) fn poll_skip(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        seek: bool,
+    ) -> Poll<Result<()>> {
         if TRACING_ENABLED {
             eprintln!(" skip: '{}', size = {}", self.path_lossy(), self.size());
         }
         let this = self.project();
-        let header = this.header;
-        this.archive.as_mut().poll_skip_entry(cx, header)
+        let len = *this.len;
+        let res = ready!(this.archive.as_mut().poll_skip_entry(cx, len, seek));
+        let path = || entry_path(this.header, this.ext).into_owned();
+        Poll::Ready(res.map_err(|err| with_path(err, *this.index, path)))
     }
 }
 
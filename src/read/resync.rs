@@ -0,0 +1,145 @@
+use std::fmt;
+use std::io::Result;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::shared::buffer::ReadableRegion;
+use crate::shared::state::State;
+use crate::{Archive, BLOCK_SIZE, TRACING_ENABLED};
+
+use super::ArchiveError;
+
+type Callback = Box<dyn FnMut(DamagedRegion) + Send + Sync>;
+
+/// A part of an archive skipped over when reading with
+/// [resynchronization][Archive::set_resync] enabled.
+#[derive(Debug)]
+pub struct DamagedRegion {
+    /// The offset in the archive of the first byte skipped. This is that of
+    /// the damaged header, or of any extension entries preceding it.
+    pub offset: u64,
+    /// The number of bytes skipped.
+    pub len: u64,
+    /// The error found in the damaged header.
+    pub error: ArchiveError,
+}
+
+/// Skips over damaged headers, if enabled, by scanning for the next block
+/// that is a valid header.
+#[derive(Default)]
+pub(crate) struct Resync {
+    on_damaged: Option<Callback>,
+    /// The start of the region being skipped and the error it starts with.
+    damaged: Option<(u64, ArchiveError)>,
+}
+
+impl Resync {
+    pub fn set_callback(&mut self, on_damaged: Callback) {
+        self.on_damaged = Some(on_damaged);
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.on_damaged.is_some()
+    }
+
+    #[inline]
+    pub fn is_scanning(&self) -> bool {
+        self.damaged.is_some()
+    }
+
+    /// Stops skipping a region, without reporting it.
+    pub fn reset(&mut self) {
+        self.damaged = None;
+    }
+
+    /// Reports the region being skipped as ending at `end`.
+    fn finish(&mut self, end: u64) {
+        let (offset, error) = self.damaged.take().expect("not scanning");
+        if let Some(on_damaged) = self.on_damaged.as_mut() {
+            on_damaged(DamagedRegion {
+                offset,
+                len: end - offset,
+                error,
+            });
+        }
+    }
+}
+
+impl fmt::Debug for Resync {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Resync")
+            .field("enabled", &self.is_enabled())
+            .field("damaged", &self.damaged)
+            .finish()
+    }
+}
+
+impl<R: AsyncRead> Archive<R> {
+    /// Starts skipping over the damaged header at the start of the buffer,
+    /// along with any extension entries received for it.
+    pub(super) fn start_resync(mut self: Pin<&mut Self>, error: ArchiveError) {
+        let this = self.as_mut().project();
+        let offset = this.header_pos.take().unwrap_or(*this.pos);
+        if TRACING_ENABLED {
+            eprintln!("     | rsnc: @{offset} / {error}");
+        }
+        this.ext.reset();
+        this.resync.damaged = Some((offset, error));
+        *this.state = State::ExpectingHeader;
+        self.skip_block();
+    }
+
+    /// Drops the block at the start of the buffer.
+    fn skip_block(self: Pin<&mut Self>) {
+        let this = self.project();
+        this.buf.buffered().commit(BLOCK_SIZE);
+        *this.pos += BLOCK_SIZE as u64;
+        if this.buf.buffered_bytes().is_empty() {
+            this.buf.clear();
+        }
+    }
+
+    /// Reads from the source object block by block until a valid header or
+    /// an empty block is buffered, and reports the blocks skipped until
+    /// then. Returns whether one was found before the source ended.
+    pub(super) fn poll_resync(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<bool>> {
+        loop {
+            let buffered = self.buf.buffered_bytes();
+            if buffered.len() >= BLOCK_SIZE {
+                let empty = buffered[..BLOCK_SIZE].iter().all(|b| *b == 0);
+                if empty || self.parse_header().is_ok() {
+                    let this = self.project();
+                    this.resync.finish(*this.pos);
+                    return Poll::Ready(Ok(true));
+                }
+
+                self.as_mut().skip_block();
+                continue;
+            }
+
+            let mut this = self.as_mut().project();
+            this.buf.compact();
+            let mut buf = ReadBuf::new(this.buf.available_bytes_mut());
+            ready!(this.io.as_mut().poll_read(cx, &mut buf))?;
+
+            let bytes_read = buf.filled().len();
+            if bytes_read == 0 {
+                // The source ended in the damaged region, along with what
+                // is left of the archive.
+                let rem = this.buf.buffered_bytes().len();
+                this.buf.clear();
+                *this.pos += rem as u64;
+                this.resync.finish(*this.pos);
+                *this.state = State::ReceivedEof;
+                return Poll::Ready(Ok(false));
+            }
+            this.buf.available().commit(bytes_read);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind, Result, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+use tokio::io::AsyncSeek;
+
+/// Seeks the source of an archive, if it implements [AsyncSeek]. The archive
+/// only knows its source can be read, so the seek functions are picked up
+/// by the methods that need them.
+pub(crate) struct Seeker<T> {
+    ops: Option<Ops<T>>,
+    /// Whether skipping an entry seeks past its data.
+    skip: bool,
+    /// Whether a seek has been started but not completed yet.
+    pending: bool,
+}
+
+struct Ops<T> {
+    start: fn(Pin<&mut T>, SeekFrom) -> Result<()>,
+    complete: fn(Pin<&mut T>, &mut Context<'_>) -> Poll<Result<u64>>,
+}
+
+impl<T> Seeker<T> {
+    pub fn enable(&mut self)
+    where
+        T: AsyncSeek,
+    {
+        self.ops = Some(Ops {
+            start: T::start_seek,
+            complete: T::poll_complete,
+        });
+    }
+
+    pub fn set_skip(&mut self, skip: bool)
+    where
+        T: AsyncSeek,
+    {
+        self.enable();
+        self.skip = skip;
+    }
+
+    #[inline]
+    pub fn skips(&self) -> bool {
+        self.skip && self.ops.is_some()
+    }
+
+    /// Starts seeking from offset `from` to offset `to`, relative to the
+    /// current position of the source. The seek must be completed with
+    /// [Self::poll_complete] before reading.
+    pub fn start(&mut self, io: Pin<&mut T>, from: u64, to: u64) -> Result<()> {
+        let ops = self.ops.as_ref().expect("seeking is not enabled");
+        let offset = i64::try_from(i128::from(to) - i128::from(from))
+            .map_err(|_| IoError::new(ErrorKind::InvalidInput, "seek offset overflow"))?;
+        (ops.start)(io, SeekFrom::Current(offset))?;
+        self.pending = true;
+        Ok(())
+    }
+
+    /// Completes the seek started last, if any.
+    pub fn poll_complete(&mut self, io: Pin<&mut T>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if let (true, Some(ops)) = (self.pending, &self.ops) {
+            ready!((ops.complete)(io, cx))?;
+            self.pending = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T> Default for Seeker<T> {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            ops: None,
+            skip: false,
+            pending: false,
+        }
+    }
+}
+
+impl<T> fmt::Debug for Seeker<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Seeker")
+            .field("enabled", &self.ops.is_some())
+            .field("skip", &self.skip)
+            .field("pending", &self.pending)
+            .finish()
+    }
+}
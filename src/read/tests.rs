@@ -1,11 +1,12 @@
 use std::io;
 use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
 
 use tokio::io::{AsyncRead, AsyncReadExt};
 
-use crate::Archive;
 use crate::shared::block::BLOCK_SIZE;
 use crate::shared::test::*;
+use crate::{Archive, ArchiveError, ErrorLocation, Header, HeaderField, ReadError, ReadPhase};
 
 const FILES: [(&str, usize); 4] = [("512", 512), ("1024", 1024), ("500", 500), ("1000", 1000)];
 
@@ -278,3 +279,1493 @@ async fn unexpected_eof() {
         }
     }
 }
+
+async fn archive_error(data: &[u8]) -> ArchiveError {
+    let archive = Archive::with_capacity(io::Cursor::new(data), NonZeroUsize::new(1).unwrap());
+    let err = read_archive(archive).await.unwrap_err();
+    *err.into_inner().unwrap().downcast().unwrap()
+}
+
+#[tokio::test]
+async fn archive_errors() {
+    let data = make_archive_data(&FILES);
+    let at = |entry, path: Option<&str>, offset| ErrorLocation {
+        entry,
+        path: path.map(|path| path.as_bytes().to_vec()),
+        offset,
+    };
+
+    // Entries start at 0, 1024, 2560 and 3584, and the trailer at 5120.
+    let cases = [
+        (1024 + 300, ReadPhase::Header, at(1, None, 1324)),
+        (1536 + 100, ReadPhase::Data, at(1, Some("1024"), 1636)),
+        (3072 + 505, ReadPhase::Alignment, at(2, Some("500"), 3577)),
+        (5120, ReadPhase::Header, at(4, None, 5120)),
+        (5632 + 10, ReadPhase::Trailer, at(4, None, 5642)),
+    ];
+    for (len, expected, location) in cases {
+        match archive_error(&data[..len]).await {
+            ArchiveError::UnexpectedEof { phase, at } => {
+                assert_eq!((phase, at), (expected, location));
+            }
+            err => panic!("unexpected error: {err}"),
+        }
+    }
+
+    let mut corrupt = data.clone();
+    corrupt[2560] ^= 1;
+    let err = archive_error(&corrupt).await;
+    assert!(matches!(
+        err,
+        ArchiveError::InvalidChecksum { at: ref loc, .. } if *loc == at(2, None, 2560)
+    ));
+    assert!(err.to_string().ends_with("; entry = 2, offset = 2560"));
+
+    let mut corrupt = data.clone();
+    corrupt[5632 + 100] = 1;
+    let err = archive_error(&corrupt).await;
+    assert!(
+        matches!(err, ArchiveError::NonZeroTrailer { at: ref loc } if *loc == at(4, None, 5632))
+    );
+
+    let mut corrupt = data.clone();
+    let mut header = make_entry_header("500", 500);
+    header.as_old_mut().size = *b"12345678901\0";
+    header.set_cksum();
+    corrupt[2560..3072].copy_from_slice(header.as_bytes());
+    let err = archive_error(&corrupt).await;
+    let expected = at(2, Some("500"), 2560);
+    assert!(matches!(
+        err,
+        ArchiveError::InvalidField { field: HeaderField::Size, at: ref loc } if *loc == expected
+    ));
+
+    let mut corrupt = data.clone();
+    let mut header = make_entry_header("500", 500);
+    header.set_entry_type(tar::EntryType::new(b'M'));
+    header.set_cksum();
+    corrupt[2560..3072].copy_from_slice(header.as_bytes());
+    let err = archive_error(&corrupt).await;
+    assert!(matches!(
+        err,
+        ArchiveError::UnsupportedType { typeflag: b'M', at: ref loc } if *loc == expected
+    ));
+
+    let mut corrupt = data.clone();
+    let mut header = make_entry_header("1024", 1024);
+    header.as_old_mut().uid = *b"bad\0\0\0\0\0";
+    header.set_cksum();
+    corrupt[1024..1536].copy_from_slice(header.as_bytes());
+    let mut archive = Archive::new(io::Cursor::new(&corrupt));
+    archive
+        .next_entry()
+        .await
+        .unwrap()
+        .unwrap()
+        .skip()
+        .await
+        .unwrap();
+    let entry = archive.next_entry().await.unwrap().unwrap();
+    let err = entry.uid().unwrap_err();
+    let err = err
+        .into_inner()
+        .unwrap()
+        .downcast::<ArchiveError>()
+        .unwrap();
+    assert!(matches!(
+        *err,
+        ArchiveError::InvalidField { field: HeaderField::Uid, at: ref loc }
+            if *loc == at(1, Some("1024"), 1024)
+    ));
+}
+
+#[tokio::test]
+async fn extension_errors() {
+    let at = |path: Option<&str>| ErrorLocation {
+        entry: 1,
+        path: path.map(|path| path.as_bytes().to_vec()),
+        offset: 1024,
+    };
+    let archive_with = |records: &[(&str, &str)]| {
+        let mut data = Vec::new();
+        append_entry(&mut data, "a", 100);
+        append_pax_header(&mut data, tar::EntryType::XHeader, records);
+        append_entry(&mut data, "file", 10);
+        data.extend(make_eof_data());
+        data
+    };
+
+    let data = archive_with(&[("path", "renamed"), ("size", "xyz")]);
+    let err = archive_error(&data).await;
+    assert!(matches!(
+        err,
+        ArchiveError::InvalidExtension { key: Some(ref key), at: ref loc }
+            if key == "size" && *loc == at(Some("renamed"))
+    ));
+
+    // The extensions are gone after the error, so the archive is left
+    // unusable rather than yielding the entry without them.
+    let mut archive = Archive::new(io::Cursor::new(&data));
+    archive
+        .next_entry()
+        .await
+        .unwrap()
+        .unwrap()
+        .skip()
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        let err = archive.next_entry().await.unwrap_err();
+        let err = err
+            .into_inner()
+            .unwrap()
+            .downcast::<ArchiveError>()
+            .unwrap();
+        assert!(matches!(*err, ArchiveError::InvalidExtension { .. }));
+    }
+
+    let mut data = archive_with(&[("path", "renamed")]);
+    data[1024 + BLOCK_SIZE] = b'9';
+    let err = archive_error(&data).await;
+    assert!(matches!(
+        err,
+        ArchiveError::InvalidExtension { key: None, at: ref loc } if *loc == at(None)
+    ));
+
+    let data = archive_with(&[("path", "renamed")]);
+    let mut archive = Archive::new(io::Cursor::new(&data));
+    archive.set_max_extension_size(10);
+    let err = read_archive(archive).await.unwrap_err();
+    let err = err
+        .into_inner()
+        .unwrap()
+        .downcast::<ArchiveError>()
+        .unwrap();
+    assert!(matches!(
+        *err,
+        ArchiveError::ExtensionTooLarge { max: 10, at: ref loc, .. } if *loc == at(None)
+    ));
+
+    let data = archive_with(&[("GNU.sparse.size", "100"), ("GNU.sparse.map", "0,50")]);
+    let err = archive_error(&data).await;
+    assert!(matches!(
+        err,
+        ArchiveError::InvalidSparseMap { at: ref loc } if *loc == at(Some("file"))
+    ));
+
+    let data = archive_with(&[("uid", "abc")]);
+    let mut archive = Archive::new(io::Cursor::new(&data));
+    archive
+        .next_entry()
+        .await
+        .unwrap()
+        .unwrap()
+        .skip()
+        .await
+        .unwrap();
+    let entry = archive.next_entry().await.unwrap().unwrap();
+    let err = entry.uid().unwrap_err();
+    let err = err
+        .into_inner()
+        .unwrap()
+        .downcast::<ArchiveError>()
+        .unwrap();
+    assert!(matches!(
+        *err,
+        ArchiveError::InvalidExtension { key: Some(ref key), at: ref loc }
+            if key == "uid" && *loc == at(Some("file"))
+    ));
+}
+
+async fn read_paths(mut archive: Archive<io::Cursor<&[u8]>>) -> io::Result<Vec<String>> {
+    let mut paths = Vec::new();
+    while let Some(mut entry) = archive.next_entry().await? {
+        entry.skip().await?;
+        paths.push(entry.path_lossy());
+    }
+    Ok(paths)
+}
+
+#[tokio::test]
+async fn ignore_zeros() {
+    let first = make_archive_data(&[("a", 100), ("b", 600)]);
+    let second = make_archive_data(&[("c", 0), ("d", 1000)]);
+    let mut data = [&first[..], &second[..]].concat();
+    // Archives are often padded to a multiple of 20 blocks.
+    data.resize(data.len().next_multiple_of(20 * BLOCK_SIZE), 0);
+
+    let paths = read_paths(Archive::new(io::Cursor::new(&data[..]))).await;
+    assert_eq!(paths.unwrap(), ["a", "b"]);
+
+    let mut archive = Archive::new(io::Cursor::new(&data[..]));
+    archive.set_ignore_zeros(true);
+    assert_eq!(read_paths(archive).await.unwrap(), ["a", "b", "c", "d"]);
+
+    // The source may end without an end-of-archive marker, but only
+    // between entries.
+    let len = first.len() - 1024;
+    let mut archive = Archive::new(io::Cursor::new(&first[..len]));
+    archive.set_ignore_zeros(true);
+    assert_eq!(read_paths(archive).await.unwrap(), ["a", "b"]);
+
+    let mut archive = Archive::new(io::Cursor::new(&first[..len - 100]));
+    archive.set_ignore_zeros(true);
+    let err = read_paths(archive).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[tokio::test]
+async fn missing_trailer() {
+    let data = make_archive_data(&[("a", 100), ("b", 600)]);
+    let end = data.len() - 1024;
+
+    for len in [end, end + 100, end + BLOCK_SIZE, end + 1000, data.len()] {
+        let mut archive = Archive::new(io::Cursor::new(&data[..len]));
+        archive.set_allow_missing_trailer(true);
+        while let Some(mut entry) = archive.next_entry().await.unwrap() {
+            entry.skip().await.unwrap();
+        }
+        assert_eq!(archive.has_complete_trailer(), len == data.len());
+        assert_eq!(archive.position(), len as u64);
+        assert!(archive.next_entry().await.unwrap().is_none());
+
+        let archive = Archive::new(io::Cursor::new(&data[..len]));
+        let res = read_paths(archive).await;
+        match len == data.len() {
+            true => assert_eq!(res.unwrap(), ["a", "b"]),
+            false => assert_eq!(res.unwrap_err().kind(), io::ErrorKind::UnexpectedEof),
+        }
+    }
+
+    // The source must still not end in an entry.
+    let mut archive = Archive::new(io::Cursor::new(&data[..end - 100]));
+    archive.set_allow_missing_trailer(true);
+    let err = read_paths(archive).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+async fn resync_archive(data: &[u8]) -> (Vec<String>, Vec<(u64, u64)>) {
+    let regions = Arc::new(Mutex::new(Vec::new()));
+    let mut archive = Archive::with_capacity(io::Cursor::new(data), NonZeroUsize::new(1).unwrap());
+    archive.set_resync({
+        let regions = regions.clone();
+        move |region| {
+            assert!(matches!(region.error, ArchiveError::InvalidChecksum { .. }));
+            regions.lock().unwrap().push((region.offset, region.len));
+        }
+    });
+
+    let mut paths = Vec::new();
+    while let Some(mut entry) = archive.next_entry().await.unwrap() {
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, make_entry_data(buf.len())[..buf.len()]);
+        paths.push(entry.path_lossy());
+    }
+    let regions = regions.lock().unwrap().clone();
+    (paths, regions)
+}
+
+#[tokio::test]
+async fn resync() {
+    let data = make_archive_data(&FILES);
+
+    // Entries start at 0, 1024, 2560 and 3584, and the trailer at 5120.
+    let mut corrupt = data.clone();
+    corrupt[1024] ^= 1;
+    corrupt[3584 + 100] ^= 1;
+    let (paths, regions) = resync_archive(&corrupt).await;
+    assert_eq!(paths, ["512", "500"]);
+    assert_eq!(regions, [(1024, 1536), (3584, 1536)]);
+
+    // A damaged region running to the end of the source ends the archive.
+    let (paths, regions) = resync_archive(&corrupt[..3584 + 700]).await;
+    assert_eq!(paths, ["512", "500"]);
+    assert_eq!(regions, [(1024, 1536), (3584, 700)]);
+
+    // Extension entries are skipped along with the header they apply to.
+    let mut corrupt = data[..1024].to_vec();
+    append_pax_header(&mut corrupt, tar::EntryType::XHeader, &[("path", "long")]);
+    corrupt.extend_from_slice(&data[1024..]);
+    corrupt[2048] ^= 1;
+    let (paths, regions) = resync_archive(&corrupt).await;
+    assert_eq!(paths, ["512", "500", "1000"]);
+    assert_eq!(regions, [(1024, 2560)]);
+}
+
+#[tokio::test]
+async fn misuse() {
+    let data = make_archive_data(&[("a", 0), ("b", 0), ("c", 100), ("d", 0)]);
+    let mut archive = Archive::new(io::Cursor::new(&data));
+
+    // Entries with no data need not be read, and read as empty after EOF.
+    let mut entry = archive.next_entry().await.unwrap().unwrap();
+    assert_eq!(entry.read(&mut [0; 10]).await.unwrap(), 0);
+    assert_eq!(entry.read(&mut [0; 10]).await.unwrap(), 0);
+    archive.next_entry().await.unwrap().unwrap();
+    let mut entry = archive.next_entry().await.unwrap().unwrap();
+    entry.read_exact(&mut [0; 10]).await.unwrap();
+
+    let err = archive.next_entry().await.unwrap_err();
+    let err = err.into_inner().unwrap().downcast::<ReadError>().unwrap();
+    assert!(matches!(*err, ReadError::OverlappingEntry));
+    let err = archive.next_entry().await.unwrap_err();
+    let err = err.into_inner().unwrap().downcast::<ReadError>().unwrap();
+    assert!(
+        matches!(&*err, ReadError::Poisoned(cause) if matches!(**cause, ReadError::OverlappingEntry))
+    );
+}
+
+async fn read_entries<R: AsyncRead + Unpin>(mut archive: Archive<R>) -> Vec<(String, Vec<u8>)> {
+    let mut entries = Vec::new();
+    while let Some(mut entry) = archive.next_entry().await.unwrap() {
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).await.unwrap();
+        entries.push((entry.path_lossy(), data));
+    }
+    entries
+}
+
+#[tokio::test]
+async fn short_reads() {
+    use tokio::io::AsyncWriteExt;
+
+    use crate::{EntryMetadata, Format, SparseRegion};
+
+    let long_path = "long/".repeat(30) + "path";
+    let meta = EntryMetadata::default();
+    let regions = [SparseRegion {
+        offset: 1000,
+        len: 100,
+    }];
+
+    for format in [Format::Pax, Format::Gnu] {
+        let mut data = Vec::new();
+        let mut archive = Archive::new(&mut data);
+        archive.set_format(format);
+        for (path, size) in FILES {
+            let mut entry = archive
+                .add_entry_with_metadata(path, size as u64, &meta)
+                .await
+                .unwrap();
+            entry
+                .write_all(&make_entry_data(size)[..size])
+                .await
+                .unwrap();
+        }
+        let mut entry = archive
+            .add_entry_with_metadata(&long_path, 10, &meta)
+            .await
+            .unwrap();
+        entry.write_all(&[1; 10]).await.unwrap();
+        let mut entry = archive
+            .add_sparse_entry("sparse", &regions, 2000, &meta)
+            .await
+            .unwrap();
+        entry.write_all(&[2; 100]).await.unwrap();
+        archive.finish().await.unwrap();
+
+        let expected = read_entries(Archive::new(io::Cursor::new(&data))).await;
+        assert_eq!(expected.len(), FILES.len() + 2);
+
+        for chunk in [1, 7, 100, 511, 513, 4000] {
+            eprintln!("format = {format:?}, chunk = {chunk}");
+            let io = ChunkedReader { data: &data, chunk };
+            assert_eq!(read_entries(Archive::new(io)).await, expected);
+        }
+    }
+}
+
+#[tokio::test]
+async fn seek_skipped_entries() {
+    let files = [("a", 100_000), ("b", 10), ("c", 200_000), ("d", 3000)];
+    let data = make_archive_data(&files);
+
+    for seekable in [false, true] {
+        let io = CountingReader {
+            io: io::Cursor::new(&data),
+            read: 0,
+        };
+        let mut archive = Archive::with_capacity(io, NonZeroUsize::new(2).unwrap());
+        archive.set_seekable(seekable);
+
+        let mut i = 0;
+        while let Some(mut entry) = archive.next_entry().await.unwrap() {
+            let (path, size) = files[i];
+            assert_eq!(entry.path_lossy(), path);
+            // Read part of some entries before skipping the rest, and all
+            // of the last one.
+            let want = match i {
+                0 => 0,
+                1 | 2 => 700.min(size),
+                _ => size,
+            };
+            let mut buf = vec![0; want];
+            entry.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, make_entry_data(size)[..want]);
+            entry.skip().await.unwrap();
+            i += 1;
+        }
+        assert_eq!(i, files.len());
+
+        let read = archive.into_inner().read;
+        if seekable {
+            assert!(read < data.len() / 10, "read {read} bytes");
+        } else {
+            assert_eq!(read, data.len());
+        }
+    }
+
+    // Indexing and opening entries seek, but leave skipping entries to read
+    // through them.
+    let io = CountingReader {
+        io: io::Cursor::new(&data),
+        read: 0,
+    };
+    let mut archive = Archive::with_capacity(io, NonZeroUsize::new(2).unwrap());
+    let index = archive.index().await.unwrap();
+    let read = archive.get_ref().read;
+    assert!(read < data.len() / 10, "read {read} bytes");
+    let mut entry = archive.open_entry(&index[0]).await.unwrap();
+    entry.skip().await.unwrap();
+    let read = archive.get_ref().read - read;
+    assert!(read > files[0].1, "read {read} bytes");
+}
+
+#[tokio::test]
+async fn index() {
+    use tokio::io::AsyncWriteExt;
+
+    use crate::{EntryMetadata, EntryType, Format, IndexEntry, ReadError, SparseRegion};
+
+    let long_path = "long/".repeat(30) + "path";
+    let meta = EntryMetadata::default();
+    let regions = [SparseRegion {
+        offset: 1000,
+        len: 100,
+    }];
+
+    for format in [Format::Pax, Format::Gnu] {
+        let mut data = Vec::new();
+        let mut archive = Archive::new(&mut data);
+        archive.set_format(format);
+        for (path, size) in FILES {
+            let mut entry = archive
+                .add_entry_with_metadata(path, size as u64, &meta)
+                .await
+                .unwrap();
+            entry
+                .write_all(&make_entry_data(size)[..size])
+                .await
+                .unwrap();
+        }
+        let mut entry = archive
+            .add_entry_with_metadata(&long_path, 10, &meta)
+            .await
+            .unwrap();
+        entry.write_all(&[1; 10]).await.unwrap();
+        let mut entry = archive
+            .add_sparse_entry("sparse", &regions, 2000, &meta)
+            .await
+            .unwrap();
+        entry.write_all(&[2; 100]).await.unwrap();
+        archive.finish().await.unwrap();
+
+        let expected = read_entries(Archive::new(io::Cursor::new(&data))).await;
+
+        let io = CountingReader {
+            io: io::Cursor::new(&data),
+            read: 0,
+        };
+        let mut archive = Archive::with_capacity(io, NonZeroUsize::new(1).unwrap());
+        let index = archive.index().await.unwrap();
+        assert!(archive.get_ref().read < data.len());
+
+        assert_eq!(index.len(), expected.len());
+        let mut header_offset = 0;
+        for (entry, (path, contents)) in index.iter().zip(&expected) {
+            assert_eq!(entry.path, path.as_bytes());
+            assert_eq!(entry.header_offset, header_offset);
+            assert!(entry.data_offset > entry.header_offset);
+            assert_eq!(entry.size, contents.len() as u64);
+            if entry.path != b"sparse" {
+                let start = entry.data_offset as usize;
+                assert_eq!(&data[start..start + contents.len()], contents);
+                assert_eq!(entry.kind, EntryType::Regular);
+                header_offset = (start + contents.len()).next_multiple_of(BLOCK_SIZE) as u64;
+            }
+        }
+
+        let encoded = IndexEntry::encode(&index).unwrap();
+        assert_eq!(IndexEntry::decode(&encoded).unwrap(), index);
+        for len in 0..encoded.len() {
+            let err = IndexEntry::decode(&encoded[..len]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+
+        let mut malformed = index.clone();
+        malformed[0].header_offset = malformed[0].data_offset + 1;
+        let err = IndexEntry::encode(&malformed).unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<ReadError>().unwrap();
+        assert!(matches!(*err, ReadError::MalformedIndex));
+
+        // Open entries out of order, from a fresh archive.
+        let mut archive =
+            Archive::with_capacity(io::Cursor::new(&data), NonZeroUsize::new(1).unwrap());
+        for (entry, (path, contents)) in index.iter().zip(&expected).rev() {
+            let mut found = archive.open_entry(entry).await.unwrap();
+            assert_eq!(&found.path_lossy(), path);
+            let mut buf = Vec::new();
+            found.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(&buf, contents);
+        }
+
+        let mut entry = index[0].clone();
+        entry.path = b"other".to_vec();
+        let err = archive.open_entry(&entry).await.unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<ReadError>().unwrap();
+        assert!(matches!(*err, ReadError::IndexMismatch { offset: 0 }));
+    }
+}
+
+#[tokio::test]
+async fn open_entry_errors() {
+    use tokio::io::AsyncReadExt;
+
+    use crate::{ArchiveError, ErrorLocation};
+
+    let files = [("a", 100), ("b", 1000), ("c", 1000)];
+    let data = make_archive_data(&files);
+    let mut archive = Archive::new(io::Cursor::new(&data));
+    let index = archive.index().await.unwrap();
+    assert_eq!(index.iter().map(|e| e.index).collect::<Vec<_>>(), [0, 1, 2]);
+
+    // Errors are found in the entry opened, not the one reading had reached.
+    let data = &data[..index[2].data_offset as usize + 500];
+    let mut archive = Archive::new(io::Cursor::new(data));
+    archive
+        .next_entry()
+        .await
+        .unwrap()
+        .unwrap()
+        .skip()
+        .await
+        .unwrap();
+    let mut entry = archive.open_entry(&index[2]).await.unwrap();
+    let err = entry.read_to_end(&mut Vec::new()).await.unwrap_err();
+    let err = err
+        .into_inner()
+        .unwrap()
+        .downcast::<ArchiveError>()
+        .unwrap();
+    let expected = ErrorLocation {
+        entry: 2,
+        path: Some(b"c".to_vec()),
+        offset: index[2].data_offset + 500,
+    };
+    assert_eq!(err.location(), &expected);
+}
+
+type PaxEntry<'a> = (&'a [(&'a str, &'a str)], &'a str, usize);
+
+fn make_pax_archive_data(entries: &[PaxEntry]) -> Vec<u8> {
+    let mut builder = tar::Builder::new(Vec::new());
+    for (pax, path, size) in entries {
+        let data = make_entry_data(*size);
+        let header = make_entry_header(path, *size);
+        builder
+            .append_pax_extensions(pax.iter().map(|(k, v)| (*k, v.as_bytes())))
+            .unwrap();
+        builder.append(&header, &data[..*size]).unwrap();
+    }
+    builder.into_inner().unwrap()
+}
+
+#[tokio::test]
+async fn pax_extended_headers() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let long_path = "long/".repeat(40) + "path";
+    let entries: [PaxEntry; 3] = [
+        (
+            &[
+                ("path", &long_path),
+                ("linkpath", "target"),
+                ("uid", "4294967296"),
+                ("gname", "staff"),
+                ("mtime", "1700000000.5"),
+            ],
+            "short",
+            1000,
+        ),
+        // Entries without extensions are not affected by preceding ones.
+        (&[], "plain", 500),
+        (&[("path", "overridden"), ("path", "twice")], "once", 0),
+    ];
+    let data = make_pax_archive_data(&entries);
+
+    for cap in [1, 10] {
+        let io = io::Cursor::new(data.as_slice());
+        let mut archive = Archive::with_capacity(io, NonZeroUsize::new(cap).unwrap());
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), long_path);
+        assert_eq!(entry.link_name().unwrap().as_ref(), b"target");
+        assert_eq!(entry.uid().unwrap(), 1 << 32);
+        assert_eq!(entry.groupname(), Some(&b"staff"[..]));
+        assert_eq!(
+            entry.mtime().unwrap(),
+            UNIX_EPOCH + Duration::new(1_700_000_000, 500_000_000)
+        );
+        assert_eq!(entry.pax_attributes().len(), 5);
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, &make_entry_data(1000)[..1000]);
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), "plain");
+        assert!(entry.link_name().is_none());
+        assert!(entry.pax_attributes().is_empty());
+        entry.skip().await.unwrap();
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), "twice");
+        assert!(entry.is_empty());
+        entry.skip().await.unwrap();
+
+        assert!(archive.next_entry().await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn pax_size_override() {
+    let size = 1500;
+    let mut header = make_entry_header("big", 0);
+    header.set_size(0);
+    header.set_cksum();
+
+    let mut builder = tar::Builder::new(Vec::new());
+    builder
+        .append_pax_extensions([("size", size.to_string().as_bytes())])
+        .unwrap();
+    // Not using `into_inner` as it would append EOF blocks.
+    let mut data = builder.get_ref().clone();
+    data.extend_from_slice(header.as_bytes());
+    data.extend(make_entry_data(size));
+    data.extend(make_eof_data());
+
+    for cap in [1, 10] {
+        let io = io::Cursor::new(data.as_slice());
+        let mut archive = Archive::with_capacity(io, NonZeroUsize::new(cap).unwrap());
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.size(), size as u64);
+        assert_eq!(entry.len(), size as u64);
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, &make_entry_data(size)[..size]);
+
+        assert!(archive.next_entry().await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn pax_malformed() {
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_ustar();
+    header.set_entry_type(tar::EntryType::XHeader);
+    let record = b"99 path=foo\n";
+    header.set_size(record.len() as u64);
+    header.set_cksum();
+    builder.append(&header, &record[..]).unwrap();
+    let data = builder.into_inner().unwrap();
+
+    let io = io::Cursor::new(data.as_slice());
+    let mut archive = Archive::new(io);
+    assert!(archive.next_entry().await.is_err());
+}
+
+fn append_pax_header(data: &mut Vec<u8>, kind: tar::EntryType, records: &[(&str, &str)]) {
+    let pax = make_pax_data(records);
+    let mut header = Header::new_ustar();
+    header.set_path("pax_global_header").unwrap();
+    header.set_entry_type(kind);
+    header.set_size(pax.len() as u64);
+    header.set_cksum();
+    data.extend_from_slice(header.as_bytes());
+    data.extend_from_slice(&pax);
+    data.resize(data.len().next_multiple_of(BLOCK_SIZE), 0);
+}
+
+fn append_entry(data: &mut Vec<u8>, path: &str, size: usize) {
+    data.extend_from_slice(make_entry_header(path, size).as_bytes());
+    data.extend(make_entry_data(size));
+}
+
+#[tokio::test]
+async fn pax_global_headers() {
+    let commit = "0123456789abcdef0123456789abcdef01234567";
+    let mut data = Vec::new();
+    append_pax_header(
+        &mut data,
+        tar::EntryType::XGlobalHeader,
+        &[("comment", commit), ("uname", "git")],
+    );
+    append_entry(&mut data, "a", 10);
+    append_pax_header(&mut data, tar::EntryType::XHeader, &[("uname", "")]);
+    append_entry(&mut data, "b", 10);
+    append_pax_header(
+        &mut data,
+        tar::EntryType::XGlobalHeader,
+        &[("uname", "bot")],
+    );
+    append_entry(&mut data, "c", 10);
+    data.extend(make_eof_data());
+
+    for cap in [1, 10] {
+        let io = io::Cursor::new(data.as_slice());
+        let mut archive = Archive::with_capacity(io, NonZeroUsize::new(cap).unwrap());
+        assert!(archive.global_attributes().is_empty());
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), "a");
+        assert_eq!(entry.username(), Some(&b"git"[..]));
+        assert_eq!(
+            entry.pax_attributes().get("comment"),
+            Some(commit.as_bytes())
+        );
+        entry.skip().await.unwrap();
+        assert_eq!(
+            archive.global_attributes().get("comment"),
+            Some(commit.as_bytes())
+        );
+
+        // An empty entry attribute unsets the global one, so the header
+        // value applies.
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), "b");
+        assert_eq!(entry.pax_attributes().username(), None);
+        assert_eq!(entry.username(), Some(&b""[..]));
+        entry.skip().await.unwrap();
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), "c");
+        assert_eq!(entry.username(), Some(&b"bot"[..]));
+        entry.skip().await.unwrap();
+
+        assert!(archive.next_entry().await.unwrap().is_none());
+        assert_eq!(archive.global_attributes().len(), 2);
+    }
+}
+
+#[tokio::test]
+async fn gnu_long_names() {
+    let long_path = "long/".repeat(40) + "path";
+    let long_link = "link/".repeat(40) + "target";
+
+    let mut builder = tar::Builder::new(Vec::new());
+    let mut header = tar::Header::new_gnu();
+    header.set_size(1000);
+    builder
+        .append_data(&mut header, &long_path, &make_entry_data(1000)[..1000])
+        .unwrap();
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    builder
+        .append_link(&mut header, "short", &long_link)
+        .unwrap();
+    let data = builder.into_inner().unwrap();
+
+    for cap in [1, 10] {
+        let io = io::Cursor::new(data.as_slice());
+        let mut archive = Archive::with_capacity(io, NonZeroUsize::new(cap).unwrap());
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), long_path);
+        assert!(entry.link_name().is_none());
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, &make_entry_data(1000)[..1000]);
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), "short");
+        assert_eq!(entry.link_name().unwrap().as_ref(), long_link.as_bytes());
+        entry.skip().await.unwrap();
+
+        assert!(archive.next_entry().await.unwrap().is_none());
+    }
+
+    let io = io::Cursor::new(data.as_slice());
+    let mut archive = Archive::new(io);
+    archive.set_max_extension_size(100);
+    let err = archive.next_entry().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+/// The data regions of a sparse file with holes in between and at the end.
+const SPARSE_REGIONS: [(u64, u64); 6] = [
+    (0, 100),
+    (1000, 600),
+    (2000, 1),
+    (4096, 512),
+    (6000, 1000),
+    (9000, 10),
+];
+const SPARSE_SIZE: u64 = 10000;
+
+/// Returns the contents of the sparse file and the data of its regions.
+fn make_sparse_data() -> (Vec<u8>, Vec<u8>) {
+    let mut contents = vec![0u8; SPARSE_SIZE as usize];
+    let mut data = Vec::new();
+    let fill = make_entry_data(SPARSE_SIZE as usize);
+    for (offset, len) in SPARSE_REGIONS {
+        let range = offset as usize..(offset + len) as usize;
+        contents[range.clone()].copy_from_slice(&fill[range.clone()]);
+        data.extend_from_slice(&fill[range]);
+    }
+    (contents, data)
+}
+
+async fn expect_sparse_entry(data: &[u8], path: &str) {
+    let (contents, _) = make_sparse_data();
+
+    for cap in [1, 10] {
+        let io = io::Cursor::new(data);
+        let mut archive = Archive::with_capacity(io, NonZeroUsize::new(cap).unwrap());
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), path);
+        assert_eq!(entry.size(), SPARSE_SIZE);
+        let regions = entry.sparse_map().unwrap();
+        let regions = regions.iter().map(|r| (r.offset, r.len));
+        assert!(regions.eq(SPARSE_REGIONS));
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, contents);
+
+        // Skipping works just as well, and the next entry is unaffected.
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), path);
+        assert!(entry.sparse_map().is_some());
+        entry.skip().await.unwrap();
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), "plain");
+        assert!(entry.sparse_map().is_none());
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, &make_entry_data(10)[..10]);
+
+        assert!(archive.next_entry().await.unwrap().is_none());
+    }
+}
+
+#[tokio::test]
+async fn sparse_gnu() {
+    let (_, sparse_data) = make_sparse_data();
+
+    let mut header = Header::new_gnu();
+    header.set_path("sparse").unwrap();
+    header.set_entry_type(tar::EntryType::GNUSparse);
+    header.set_size(sparse_data.len() as u64);
+    let gnu = header.as_gnu_mut().unwrap();
+    gnu.set_real_size(SPARSE_SIZE);
+    gnu.set_is_extended(true);
+    let mut ext = tar::GnuExtSparseHeader::new();
+    let (first, rest) = SPARSE_REGIONS.split_at(4);
+    for (sparse, (offset, len)) in gnu.sparse.iter_mut().zip(first) {
+        sparse.set_offset(*offset);
+        sparse.set_length(*len);
+    }
+    for (sparse, (offset, len)) in ext.sparse_mut().iter_mut().zip(rest) {
+        sparse.set_offset(*offset);
+        sparse.set_length(*len);
+    }
+    header.set_cksum();
+
+    let mut entry = header.as_bytes().to_vec();
+    entry.extend_from_slice(ext.as_bytes());
+    entry.extend_from_slice(&sparse_data);
+    entry.resize(entry.len().next_multiple_of(BLOCK_SIZE), 0);
+
+    let mut data = [&entry[..], &entry[..]].concat();
+    append_entry(&mut data, "plain", 10);
+    data.extend(make_eof_data());
+    expect_sparse_entry(&data, "sparse").await;
+
+    // The entry is cut short of the data in the sparse map.
+    let mut header = Header::new_gnu();
+    header.set_path("sparse").unwrap();
+    header.set_entry_type(tar::EntryType::GNUSparse);
+    header.set_size(10);
+    let gnu = header.as_gnu_mut().unwrap();
+    gnu.set_real_size(100);
+    gnu.sparse[0].set_offset(0);
+    gnu.sparse[0].set_length(20);
+    header.set_cksum();
+    let mut data = header.as_bytes().to_vec();
+    data.extend(make_entry_data(10));
+    data.extend(make_eof_data());
+    let mut archive = Archive::new(io::Cursor::new(data));
+    let err = archive.next_entry().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn sparse_pax() {
+    let (_, sparse_data) = make_sparse_data();
+    let size = SPARSE_SIZE.to_string();
+    let map = SPARSE_REGIONS
+        .iter()
+        .flat_map(|(offset, len)| [offset.to_string(), len.to_string()])
+        .collect::<Vec<_>>();
+
+    let append_sparse_entry = |data: &mut Vec<u8>, records: &[(&str, &str)], map: &[u8]| {
+        append_pax_header(data, tar::EntryType::XHeader, records);
+        let mut entry = map.to_vec();
+        entry.resize(map.len().next_multiple_of(BLOCK_SIZE), 0);
+        entry.extend_from_slice(&sparse_data);
+        let header = make_entry_header("GNUSparseFile.0/sparse", entry.len());
+        data.extend_from_slice(header.as_bytes());
+        data.extend_from_slice(&entry);
+        data.resize(data.len().next_multiple_of(BLOCK_SIZE), 0);
+    };
+
+    // 0.0: the map is in repeated records.
+    let mut records = vec![
+        ("GNU.sparse.size", &*size),
+        ("GNU.sparse.numblocks", "6"),
+        ("path", "sparse"),
+    ];
+    for pair in map.chunks(2) {
+        records.push(("GNU.sparse.offset", &pair[0]));
+        records.push(("GNU.sparse.numbytes", &pair[1]));
+    }
+    let mut data = Vec::new();
+    append_sparse_entry(&mut data, &records, b"");
+    append_sparse_entry(&mut data, &records, b"");
+    append_entry(&mut data, "plain", 10);
+    data.extend(make_eof_data());
+    expect_sparse_entry(&data, "sparse").await;
+
+    // 0.1: the map is in a single record.
+    let joined = map.join(",");
+    let records = [
+        ("GNU.sparse.size", &*size),
+        ("GNU.sparse.map", &joined),
+        ("GNU.sparse.name", "sparse"),
+    ];
+    let mut data = Vec::new();
+    append_sparse_entry(&mut data, &records, b"");
+    append_sparse_entry(&mut data, &records, b"");
+    append_entry(&mut data, "plain", 10);
+    data.extend(make_eof_data());
+    expect_sparse_entry(&data, "sparse").await;
+
+    // 1.0: the map precedes the entry data. Make it span more than a block.
+    let records = [
+        ("GNU.sparse.major", "1"),
+        ("GNU.sparse.minor", "0"),
+        ("GNU.sparse.realsize", &*size),
+        ("GNU.sparse.name", "sparse"),
+    ];
+    let mut lines = format!("{:0>600}\n", SPARSE_REGIONS.len());
+    for value in &map {
+        lines.push_str(&format!("{value}\n"));
+    }
+    let mut data = Vec::new();
+    append_sparse_entry(&mut data, &records, lines.as_bytes());
+    append_sparse_entry(&mut data, &records, lines.as_bytes());
+    append_entry(&mut data, "plain", 10);
+    data.extend(make_eof_data());
+    expect_sparse_entry(&data, "sparse").await;
+
+    // The sparse map must fit in the entry.
+    let mut data = Vec::new();
+    append_pax_header(&mut data, tar::EntryType::XHeader, &records);
+    append_entry(&mut data, "sparse", 10);
+    data.extend(make_eof_data());
+    let mut archive = Archive::new(io::Cursor::new(data));
+    let err = archive.next_entry().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[tokio::test]
+async fn base256_fields() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    let size = (8 << 30) + 100;
+    let mut header = Header::new_gnu();
+    header.set_path("big").unwrap();
+    header.set_size(size);
+    header.set_uid(1 << 32);
+    header.set_gid(1 << 22);
+    header.set_mtime(1 << 40);
+    header.set_cksum();
+    assert_eq!(header.as_old().size[0], 0x80);
+    assert_eq!(header.as_old().uid[0], 0x80);
+
+    // The archive is cut short well before the end of the entry data.
+    let mut data = header.as_bytes().to_vec();
+    data.extend(make_entry_data(1000));
+
+    let mut archive = Archive::new(io::Cursor::new(data));
+    let mut entry = archive.next_entry().await.unwrap().unwrap();
+    assert_eq!(entry.size(), size);
+    assert_eq!(entry.len(), size);
+    assert_eq!(entry.uid().unwrap(), 1 << 32);
+    assert_eq!(entry.gid().unwrap(), 1 << 22);
+    assert_eq!(
+        entry.mtime().unwrap(),
+        UNIX_EPOCH + Duration::from_secs(1 << 40)
+    );
+    let mut buf = vec![0u8; 1000];
+    entry.read_exact(&mut buf).await.unwrap();
+    assert_eq!(buf, &make_entry_data(1000)[..1000]);
+    let err = entry.skip().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    // Negative times are two's complement.
+    let mut header = make_entry_header("old", 0);
+    header.as_old_mut().mtime = [0xff; 12];
+    header.as_old_mut().mtime[11] = 0xf6;
+    header.set_cksum();
+    let mut data = header.as_bytes().to_vec();
+    data.extend(make_eof_data());
+    let mut archive = Archive::new(io::Cursor::new(data));
+    let entry = archive.next_entry().await.unwrap().unwrap();
+    assert_eq!(entry.mtime().unwrap(), UNIX_EPOCH - Duration::from_secs(10));
+}
+
+#[tokio::test]
+async fn header_kinds() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::HeaderKind;
+
+    // Global attributes don't make entries PAX ones.
+    let mut data = Vec::new();
+    append_pax_header(
+        &mut data,
+        tar::EntryType::XGlobalHeader,
+        &[("comment", "hi")],
+    );
+
+    let mut header = Header::new_old();
+    header.set_path("v7").unwrap();
+    header.set_size(0);
+    header.set_cksum();
+    data.extend_from_slice(header.as_bytes());
+
+    append_entry(&mut data, "ustar", 0);
+
+    let mut header = Header::new_gnu();
+    header.set_path("gnu").unwrap();
+    header.set_size(0);
+    let gnu = header.as_gnu_mut().unwrap();
+    gnu.set_atime(100);
+    gnu.set_ctime(200);
+    header.set_cksum();
+    data.extend_from_slice(header.as_bytes());
+
+    let star_path = "p".repeat(131) + "/star";
+    data.extend_from_slice(make_star_header(&star_path, 300, 400).as_bytes());
+
+    append_pax_header(&mut data, tar::EntryType::XHeader, &[("atime", "1.5")]);
+    append_entry(&mut data, "pax", 0);
+    data.extend(make_eof_data());
+
+    let secs = |secs| Some(UNIX_EPOCH + Duration::from_secs(secs));
+    let expected = [
+        ("v7", HeaderKind::V7, HeaderKind::V7, None, None),
+        ("ustar", HeaderKind::Ustar, HeaderKind::Ustar, None, None),
+        (
+            "gnu",
+            HeaderKind::Gnu,
+            HeaderKind::Gnu,
+            secs(100),
+            secs(200),
+        ),
+        (
+            &star_path,
+            HeaderKind::Star,
+            HeaderKind::Star,
+            secs(300),
+            secs(400),
+        ),
+        (
+            "pax",
+            HeaderKind::Pax,
+            HeaderKind::Ustar,
+            Some(UNIX_EPOCH + Duration::from_millis(1500)),
+            None,
+        ),
+    ];
+
+    let mut archive = Archive::new(io::Cursor::new(data));
+    for (path, kind, header_kind, atime, ctime) in expected {
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), path);
+        assert_eq!(entry.kind(), kind);
+        assert_eq!(entry.header_kind(), header_kind);
+        assert_eq!(entry.atime().unwrap(), atime);
+        assert_eq!(entry.ctime().unwrap(), ctime);
+        entry.skip().await.unwrap();
+    }
+    assert!(archive.next_entry().await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn signed_checksums() {
+    use crate::ChecksumKind;
+
+    let path = "sörmland/ħëllø";
+    let mut header = make_entry_header(path, 10);
+    // Recompute the checksum like Sun tar did, summing signed bytes.
+    let bytes = header.as_bytes();
+    let signed = bytes[..148]
+        .iter()
+        .chain(&bytes[156..])
+        .fold(8 * 32, |sum, b| sum + *b as i8 as i64);
+    let cksum = format!("{signed:06o}\0 ");
+    header.as_old_mut().cksum.copy_from_slice(cksum.as_bytes());
+
+    let mut data = header.as_bytes().to_vec();
+    data.extend(make_entry_data(10));
+    append_entry(&mut data, "plain", 10);
+    data.extend(make_eof_data());
+
+    let mut archive = Archive::new(io::Cursor::new(data));
+    let mut entry = archive.next_entry().await.unwrap().unwrap();
+    assert_eq!(entry.path_lossy(), path);
+    assert_eq!(entry.checksum_kind().unwrap(), ChecksumKind::Signed);
+    entry.skip().await.unwrap();
+    let mut entry = archive.next_entry().await.unwrap().unwrap();
+    assert_eq!(entry.checksum_kind().unwrap(), ChecksumKind::Unsigned);
+    entry.skip().await.unwrap();
+    assert!(archive.next_entry().await.unwrap().is_none());
+}
+
+#[cfg(all(unix, feature = "fs"))]
+#[tokio::test]
+async fn unpack() {
+    use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+    use std::path::Path;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use tokio::io::AsyncWriteExt;
+
+    use crate::{EntryMetadata, EntryType, PathPolicy, SparseRegion, UnpackSummary, Unpacked};
+
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let (contents, sparse_data) = make_sparse_data();
+    let regions: Vec<_> = SPARSE_REGIONS
+        .iter()
+        .map(|&(offset, len)| SparseRegion { offset, len })
+        .collect();
+
+    let mut data = Vec::new();
+    let mut archive = Archive::new(&mut data);
+    let meta = |kind, mode, link_name: Option<&str>| EntryMetadata {
+        kind,
+        mode,
+        mtime,
+        link_name: link_name.map(Into::into),
+        ..Default::default()
+    };
+
+    let dir = meta(EntryType::Directory, 0o750, None);
+    let file = meta(EntryType::Regular, 0o640, None);
+    archive
+        .add_entry_with_metadata("dir", 0, &dir)
+        .await
+        .unwrap();
+    let mut entry = archive
+        .add_entry_with_metadata("dir/file", 5, &file)
+        .await
+        .unwrap();
+    entry.write_all(b"hello").await.unwrap();
+    let symlink = meta(EntryType::Symlink, 0o777, Some("file"));
+    archive
+        .add_entry_with_metadata("dir/link", 0, &symlink)
+        .await
+        .unwrap();
+    let hard_link = meta(EntryType::Link, 0o640, Some("dir/file"));
+    archive
+        .add_entry_with_metadata("dir/hard", 0, &hard_link)
+        .await
+        .unwrap();
+    let fifo = meta(EntryType::Fifo, 0o600, None);
+    archive
+        .add_entry_with_metadata("./fifo", 0, &fifo)
+        .await
+        .unwrap();
+    let mut entry = archive
+        .add_sparse_entry("sparse", &regions, SPARSE_SIZE, &file)
+        .await
+        .unwrap();
+    entry.write_all(&sparse_data).await.unwrap();
+    archive
+        .add_entry_with_metadata("new/file", 0, &file)
+        .await
+        .unwrap();
+
+    // Entries that point outside of the destination are skipped.
+    let mut header = Header::new_ustar();
+    header.as_old_mut().name[..7].copy_from_slice(b"../evil");
+    header.set_size(0);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive.add_entry(header).await.unwrap();
+    archive.finish().await.unwrap();
+
+    let dst = std::env::temp_dir().join(format!("tario-unpack-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dst);
+
+    let mut archive = Archive::new(io::Cursor::new(&data));
+    archive.set_path_policy(PathPolicy::Skip);
+    let summary = archive.unpack(&dst).await.unwrap();
+    let expected = UnpackSummary {
+        files: 3,
+        directories: 1,
+        symlinks: 1,
+        hard_links: 1,
+        fifos: 1,
+        skipped: 1,
+        bytes: 5 + SPARSE_SIZE,
+    };
+    assert_eq!(summary, expected);
+
+    let mode = |path: &str| std::fs::symlink_metadata(dst.join(path)).unwrap();
+    assert_eq!(mode("dir").permissions().mode() & 0o777, 0o750);
+    assert_eq!(mode("dir").modified().unwrap(), mtime);
+    assert_eq!(std::fs::read(dst.join("dir/file")).unwrap(), b"hello");
+    assert_eq!(mode("dir/file").permissions().mode() & 0o777, 0o640);
+    assert_eq!(mode("dir/file").modified().unwrap(), mtime);
+    assert_eq!(
+        std::fs::read_link(dst.join("dir/link")).unwrap(),
+        Path::new("file")
+    );
+    assert_eq!(mode("dir/link").modified().unwrap(), mtime);
+    assert_eq!(mode("dir/hard").ino(), mode("dir/file").ino());
+    assert!(mode("fifo").file_type().is_fifo());
+    assert_eq!(std::fs::read(dst.join("sparse")).unwrap(), contents);
+    assert!(dst.join("new/file").exists());
+    assert!(!dst.parent().unwrap().join("evil").exists());
+
+    // Unpacking again replaces what is there, one entry at a time.
+    let mut archive = Archive::new(io::Cursor::new(&data));
+    archive.set_path_policy(PathPolicy::Skip);
+    while let Some(mut entry) = archive.next_entry().await.unwrap() {
+        let unpacked = entry.unpack_in(&dst).await.unwrap();
+        if entry.path_lossy() == "dir/file" {
+            assert_eq!(unpacked, Unpacked::File(5));
+        }
+    }
+    assert_eq!(std::fs::read(dst.join("dir/file")).unwrap(), b"hello");
+
+    std::fs::remove_dir_all(&dst).unwrap();
+}
+
+#[cfg(all(unix, feature = "fs"))]
+#[tokio::test]
+async fn unpack_path_policy() {
+    use crate::{EntryType, PathPolicy, UnpackError};
+
+    fn append(data: &mut Vec<u8>, path: &str, kind: EntryType, link_name: &str) {
+        // Header::set_path refuses the paths we are after.
+        let mut header = Header::new_ustar();
+        header.as_old_mut().name[..path.len()].copy_from_slice(path.as_bytes());
+        header.as_old_mut().linkname[..link_name.len()].copy_from_slice(link_name.as_bytes());
+        header.set_entry_type(kind);
+        header.set_size(0);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_cksum();
+        data.extend_from_slice(header.as_bytes());
+    }
+
+    let root = std::env::temp_dir().join(format!("tario-policy-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let dst = root.join("dst");
+    let outside = root.join("outside");
+    std::fs::create_dir_all(&outside).unwrap();
+
+    let unpack = async |path: &str, kind, link_name: &str, policy| {
+        let mut data = Vec::new();
+        append(&mut data, path, kind, link_name);
+        data.extend(make_eof_data());
+        let mut archive = Archive::new(io::Cursor::new(data));
+        archive.set_path_policy(policy);
+        archive.unpack(&dst).await
+    };
+
+    let file = EntryType::Regular;
+    for path in ["../evil", "/evil", "a/../../evil", "./../evil"] {
+        let err = unpack(path, file, "", PathPolicy::Reject)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let err = err.into_inner().unwrap().downcast::<UnpackError>().unwrap();
+        assert!(matches!(*err, UnpackError::UnsafePath { .. }), "{err}");
+        assert!(!root.join("evil").exists() && !dst.join("evil").exists());
+
+        let summary = unpack(path, file, "", PathPolicy::Skip).await.unwrap();
+        assert_eq!(summary.skipped, 1);
+        assert!(!root.join("evil").exists() && !dst.join("evil").exists());
+
+        let summary = unpack(path, file, "", PathPolicy::Strip).await.unwrap();
+        assert_eq!(summary.files, 1);
+        assert!(!root.join("evil").exists());
+        std::fs::remove_file(dst.join("evil")).unwrap();
+    }
+
+    // A symlink may point anywhere, but nothing is unpacked through it.
+    let link = outside.to_str().unwrap();
+    let symlink = EntryType::Symlink;
+    unpack("link", symlink, link, PathPolicy::Reject)
+        .await
+        .unwrap();
+    for policy in [PathPolicy::Reject, PathPolicy::Strip] {
+        let err = unpack("link/evil", file, "", policy).await.unwrap_err();
+        let err = err.into_inner().unwrap().downcast::<UnpackError>().unwrap();
+        assert!(matches!(*err, UnpackError::UnsafePath { .. }), "{err}");
+        let err = unpack("link", EntryType::Directory, "", policy)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+    let summary = unpack("link/evil", file, "", PathPolicy::Skip)
+        .await
+        .unwrap();
+    assert_eq!(summary.skipped, 1);
+    assert!(!outside.join("evil").exists());
+
+    // Other entries replace the symlink instead.
+    let summary = unpack("link", file, "", PathPolicy::Reject).await.unwrap();
+    assert_eq!(summary.files, 1);
+    assert!(
+        std::fs::symlink_metadata(dst.join("link"))
+            .unwrap()
+            .is_file()
+    );
+
+    // Symlinks within the destination can be unpacked through.
+    unpack("dir/file", file, "", PathPolicy::Reject)
+        .await
+        .unwrap();
+    unpack("inner", symlink, "dir", PathPolicy::Reject)
+        .await
+        .unwrap();
+    unpack("inner/other", file, "", PathPolicy::Reject)
+        .await
+        .unwrap();
+    assert!(dst.join("dir/other").exists());
+
+    // So are hard link targets.
+    let hard_link = EntryType::Link;
+    let err = unpack("hard", hard_link, "../outside", PathPolicy::Reject)
+        .await
+        .unwrap_err();
+    let err = err.into_inner().unwrap().downcast::<UnpackError>().unwrap();
+    assert!(matches!(*err, UnpackError::UnsafeLink { .. }), "{err}");
+    let summary = unpack("hard", hard_link, "../outside", PathPolicy::Skip)
+        .await
+        .unwrap();
+    assert_eq!(summary.skipped, 1);
+    unpack("hard", hard_link, "/dir/file", PathPolicy::Strip)
+        .await
+        .unwrap();
+    assert!(dst.join("hard").exists());
+
+    // Links to the destination itself are skipped, as entries at it are.
+    for policy in [PathPolicy::Reject, PathPolicy::Strip, PathPolicy::Skip] {
+        let summary = unpack("hard2", hard_link, ".", policy).await.unwrap();
+        assert_eq!(summary.skipped, 1);
+        assert!(!dst.join("hard2").exists());
+    }
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[cfg(feature = "compression")]
+async fn compress(data: &[u8], compression: crate::Compression) -> Vec<u8> {
+    use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+    // Two frames, or members, to check that all of them are read.
+    let (first, second) = data.split_at(data.len() / 2);
+    let mut out = Vec::new();
+    for data in [first, second] {
+        let out = &mut out;
+        let mut encoder: Box<dyn AsyncWrite + Unpin + '_> = match compression {
+            #[cfg(feature = "gzip")]
+            crate::Compression::Gzip => {
+                Box::new(async_compression::tokio::write::GzipEncoder::new(out))
+            }
+            #[cfg(feature = "zstd")]
+            crate::Compression::Zstd => {
+                Box::new(async_compression::tokio::write::ZstdEncoder::new(out))
+            }
+            #[cfg(feature = "xz")]
+            crate::Compression::Xz => {
+                Box::new(async_compression::tokio::write::XzEncoder::new(out))
+            }
+            #[cfg(feature = "bzip2")]
+            crate::Compression::Bzip2 => {
+                Box::new(async_compression::tokio::write::BzEncoder::new(out))
+            }
+            _ => Box::new(out),
+        };
+        encoder.write_all(data).await.unwrap();
+        encoder.shutdown().await.unwrap();
+    }
+    out
+}
+
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn decompress() {
+    use crate::Compression;
+
+    let data = make_archive_data(&FILES);
+
+    let mut enabled = vec![Compression::None];
+    enabled.extend(cfg!(feature = "gzip").then_some(Compression::Gzip));
+    enabled.extend(cfg!(feature = "zstd").then_some(Compression::Zstd));
+    enabled.extend(cfg!(feature = "xz").then_some(Compression::Xz));
+    enabled.extend(cfg!(feature = "bzip2").then_some(Compression::Bzip2));
+
+    for compression in enabled {
+        let compressed = compress(&data, compression).await;
+        let mut archive = Archive::new_auto(io::Cursor::new(compressed));
+        assert_eq!(archive.get_ref().compression(), None);
+
+        let mut n = 0;
+        while let Some(mut entry) = archive.next_entry().await.unwrap() {
+            let (path, size) = FILES[n];
+            assert_eq!(entry.path_lossy(), path);
+            let mut buf = Vec::new();
+            entry.read_to_end(&mut buf).await.unwrap();
+            assert_eq!(buf, make_entry_data(size)[..size]);
+            n += 1;
+        }
+        assert_eq!(n, FILES.len(), "{compression}");
+        assert_eq!(archive.get_ref().compression(), Some(compression));
+    }
+
+    // Archives shorter than the magic are read as plain archives.
+    let mut archive = Archive::new_auto(io::Cursor::new(b"BZh"));
+    let err = archive.next_entry().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    assert_eq!(archive.get_ref().compression(), Some(Compression::None));
+
+    // Compressions whose feature is not enabled are detected, but rejected.
+    #[cfg(not(feature = "xz"))]
+    {
+        let io = io::Cursor::new(b"\xfd7zXZ\0\0\x04\xe6\xd6\xb4\x46");
+        let mut archive = Archive::new_auto(io);
+        let err = archive.next_entry().await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,458 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind, Result, SeekFrom};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use filetime::FileTime;
+use tokio::fs;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncSeekExt, AsyncWriteExt};
+use tokio::task;
+
+use crate::shared::sparse::{Chunk, Sparse};
+use crate::{Archive, Entry};
+
+/// What [Entry::unpack_in] made of an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Unpacked {
+    /// A regular file with the given size.
+    File(u64),
+    Directory,
+    Symlink,
+    HardLink,
+    Fifo,
+    /// An entry that was not unpacked, such as a device file or an entry
+    /// with a path that points outside of the destination.
+    Skipped,
+}
+
+/// A summary of the entries written by [Archive::unpack].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct UnpackSummary {
+    pub files: u64,
+    pub directories: u64,
+    pub symlinks: u64,
+    pub hard_links: u64,
+    pub fifos: u64,
+    pub skipped: u64,
+    /// The total size of the regular files written.
+    pub bytes: u64,
+}
+
+impl UnpackSummary {
+    fn add(&mut self, unpacked: Unpacked) {
+        match unpacked {
+            Unpacked::File(size) => {
+                self.files += 1;
+                self.bytes += size;
+            }
+            Unpacked::Directory => self.directories += 1,
+            Unpacked::Symlink => self.symlinks += 1,
+            Unpacked::HardLink => self.hard_links += 1,
+            Unpacked::Fifo => self.fifos += 1,
+            Unpacked::Skipped => self.skipped += 1,
+        }
+    }
+}
+
+/// What to do with entries whose paths point outside of the destination,
+/// because they are absolute, have `..` components, or resolve through a
+/// previously unpacked symlink that points outside of it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathPolicy {
+    /// Fail with an [UnpackError].
+    #[default]
+    Reject,
+    /// Strip any leading `/` and everything up to the last `..` component,
+    /// as GNU tar does. Paths that resolve through a symlink cannot be fixed
+    /// up this way, so they are rejected.
+    Strip,
+    /// Skip the entry.
+    Skip,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct UnpackOptions {
+    pub policy: PathPolicy,
+}
+
+#[derive(Debug)]
+pub enum UnpackError {
+    /// The path of an entry points outside of the destination.
+    UnsafePath { path: PathBuf },
+    /// The target of a hard link entry points outside of the destination.
+    UnsafeLink { path: PathBuf, target: PathBuf },
+}
+
+impl UnpackError {
+    #[inline]
+    pub fn kind(&self) -> ErrorKind {
+        ErrorKind::InvalidData
+    }
+}
+
+impl std::error::Error for UnpackError {}
+
+impl fmt::Display for UnpackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsafePath { path } => {
+                format!("entry path outside of destination: {}", path.display()).fmt(f)
+            }
+            Self::UnsafeLink { path, target } => format!(
+                "link target outside of destination; path = {}, target = {}",
+                path.display(),
+                target.display()
+            )
+            .fmt(f),
+        }
+    }
+}
+
+impl From<UnpackError> for IoError {
+    #[inline]
+    fn from(value: UnpackError) -> Self {
+        IoError::new(value.kind(), value)
+    }
+}
+
+impl<T> From<UnpackError> for Result<T> {
+    #[inline]
+    fn from(value: UnpackError) -> Self {
+        Err(value.into())
+    }
+}
+
+/// The metadata of a directory, applied once everything in it is unpacked.
+pub(crate) struct DirMetadata {
+    path: PathBuf,
+    mode: u32,
+    mtime: SystemTime,
+}
+
+pub(crate) async fn unpack_archive<R: AsyncRead + Unpin>(
+    archive: &mut Archive<R>,
+    dst: &Path,
+) -> Result<UnpackSummary> {
+    let dst = canonical_dst(dst).await?;
+    let mut summary = UnpackSummary::default();
+    let mut dirs = Vec::new();
+
+    while let Some(mut entry) = archive.next_entry().await? {
+        let unpacked = unpack_entry(&mut entry, &dst, Some(&mut dirs)).await?;
+        summary.add(unpacked);
+    }
+
+    // Unpacking into a directory changes its mtime and may be forbidden
+    // by its mode, so directories are done last, innermost first.
+    dirs.sort_by(|a, b| b.path.cmp(&a.path));
+    for dir in dirs {
+        set_metadata(dir.path, dir.mode, dir.mtime).await?;
+    }
+
+    Ok(summary)
+}
+
+pub(crate) async fn unpack_in<R: AsyncRead + Unpin>(
+    entry: &mut Entry<'_, R>,
+    dst: &Path,
+) -> Result<Unpacked> {
+    let dst = canonical_dst(dst).await?;
+    unpack_entry(entry, &dst, None).await
+}
+
+/// Creates the destination if missing and returns its canonical path, which
+/// resolved paths are checked against.
+async fn canonical_dst(dst: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dst).await?;
+    fs::canonicalize(dst).await
+}
+
+async fn unpack_entry<R: AsyncRead + Unpin>(
+    entry: &mut Entry<'_, R>,
+    dst: &Path,
+    dirs: Option<&mut Vec<DirMetadata>>,
+) -> Result<Unpacked> {
+    let policy = entry.archive.unpack.policy;
+    let kind = entry.header.entry_type();
+    let is_dir = kind.is_dir() || (kind.is_file() && entry.path().ends_with(b"/"));
+    let is_file = kind.is_file() || kind.is_contiguous() || kind.is_gnu_sparse();
+
+    // A directory is unpacked through a symlink in its place, unlike other
+    // entries which replace it.
+    let path = match resolve(dst, &entry.path(), policy, is_dir).await? {
+        Some(path) if path != dst => path,
+        Some(_) => return skip(entry).await,
+        None if policy == PathPolicy::Skip => return skip(entry).await,
+        None => {
+            let path = bytes_to_path(&entry.path()).into_owned();
+            return UnpackError::UnsafePath { path }.into();
+        }
+    };
+
+    let mode = entry.header.mode()? & 0o777;
+    let mtime = entry.mtime()?;
+
+    if is_dir {
+        fs::create_dir_all(&path).await?;
+        entry.skip().await?;
+        match dirs {
+            Some(dirs) => dirs.push(DirMetadata { path, mode, mtime }),
+            None => set_metadata(path, mode, mtime).await?,
+        }
+        return Ok(Unpacked::Directory);
+    }
+
+    if !(is_file || kind.is_symlink() || kind.is_hard_link() || kind.is_fifo()) {
+        return skip(entry).await;
+    }
+
+    // Hard link targets are subject to the same checks as entry paths.
+    let link_target = if kind.is_hard_link() {
+        let target = link_name(entry)?;
+        let name = target.as_os_str().as_encoded_bytes();
+        match resolve(dst, name, policy, false).await? {
+            Some(resolved) if resolved != dst => Some(resolved),
+            Some(_) => return skip(entry).await,
+            None if policy == PathPolicy::Skip => return skip(entry).await,
+            None => {
+                let path = bytes_to_path(&entry.path()).into_owned();
+                return UnpackError::UnsafeLink { path, target }.into();
+            }
+        }
+    } else {
+        None
+    };
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    remove_existing(&path).await?;
+
+    if kind.is_symlink() {
+        let target = link_name(entry)?;
+        entry.skip().await?;
+        symlink(&target, &path).await?;
+        let mtime = FileTime::from_system_time(mtime);
+        blocking(move || filetime::set_symlink_file_times(path, mtime, mtime)).await?;
+        return Ok(Unpacked::Symlink);
+    }
+
+    if let Some(target) = link_target {
+        entry.skip().await?;
+        fs::hard_link(target, path).await?;
+        return Ok(Unpacked::HardLink);
+    }
+
+    if kind.is_fifo() {
+        entry.skip().await?;
+        mkfifo(&path, mode).await?;
+        set_metadata(path, mode, mtime).await?;
+        return Ok(Unpacked::Fifo);
+    }
+
+    let size = write_file(entry, &path).await?;
+    set_metadata(path, mode, mtime).await?;
+    Ok(Unpacked::File(size))
+}
+
+async fn skip<R: AsyncRead + Unpin>(entry: &mut Entry<'_, R>) -> Result<Unpacked> {
+    entry.skip().await?;
+    Ok(Unpacked::Skipped)
+}
+
+/// Copies the entry data into a new file, leaving holes in place of the
+/// holes of sparse entries.
+async fn write_file<R: AsyncRead + Unpin>(entry: &mut Entry<'_, R>, path: &Path) -> Result<u64> {
+    let mut file = fs::File::create(path).await?;
+    let size = entry.size();
+
+    loop {
+        if let Some(Chunk::Hole(n)) = entry.ext.sparse.as_ref().map(Sparse::chunk) {
+            let amt = usize::try_from(n.min(i64::MAX as u64)).unwrap_or(usize::MAX);
+            file.seek(SeekFrom::Current(amt as i64)).await?;
+            entry.consume(amt);
+            continue;
+        }
+
+        let buf = entry.fill_buf().await?;
+        if buf.is_empty() {
+            break;
+        }
+        file.write_all(buf).await?;
+        let amt = buf.len();
+        entry.consume(amt);
+    }
+
+    // Account for a trailing hole.
+    file.flush().await?;
+    file.set_len(size).await?;
+    Ok(size)
+}
+
+/// Resolves an entry path under `dst`, which must be canonical, according
+/// to the policy. Returns [None] if the path points outside of `dst`, also
+/// through symlinks already there. The last component is only checked if
+/// `last` is set, since anything but a directory in its place is replaced.
+async fn resolve(
+    dst: &Path,
+    path: &[u8],
+    policy: PathPolicy,
+    last: bool,
+) -> Result<Option<PathBuf>> {
+    let Some(rel) = sanitize(&bytes_to_path(path), policy) else {
+        return Ok(None);
+    };
+
+    let mut cur = dst.to_path_buf();
+    let mut components = rel.components().peekable();
+    while let Some(component) = components.next() {
+        if components.peek().is_none() && !last {
+            break;
+        }
+        cur.push(component);
+        match fs::symlink_metadata(&cur).await {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                // Dangling symlinks are rejected too, as we cannot tell
+                // where things created through them would end up.
+                match fs::canonicalize(&cur).await {
+                    Ok(target) if target.starts_with(dst) => {}
+                    _ => return Ok(None),
+                }
+            }
+            Ok(_) => {}
+            // Nothing further down exists, so there are no more symlinks.
+            Err(err) if err.kind() == ErrorKind::NotFound => break,
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(Some(dst.join(rel)))
+}
+
+/// Turns an entry path into a relative one, dropping any `.` components.
+/// Returns [None] if the path is absolute or has `..` components, unless
+/// the policy is to strip them.
+fn sanitize(path: &Path, policy: PathPolicy) -> Option<PathBuf> {
+    let strip = policy == PathPolicy::Strip;
+    let mut out = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::Prefix(_) | Component::RootDir if strip => {}
+            Component::ParentDir if strip => out.clear(),
+            Component::Prefix(_) | Component::RootDir | Component::ParentDir => return None,
+            Component::Normal(part) => out.push(part),
+        }
+    }
+    Some(out)
+}
+
+fn link_name<R>(entry: &Entry<'_, R>) -> Result<PathBuf> {
+    let name = entry
+        .link_name()
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidData, "link entry without a link name"))?;
+    Ok(bytes_to_path(&name).into_owned())
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> Cow<'_, Path> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(Path::new(OsStr::from_bytes(bytes)))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> Cow<'_, Path> {
+    match String::from_utf8_lossy(bytes) {
+        Cow::Borrowed(s) => Cow::Borrowed(Path::new(s)),
+        Cow::Owned(s) => Cow::Owned(PathBuf::from(s)),
+    }
+}
+
+/// Removes anything but a directory at `path`, so that it can be replaced.
+async fn remove_existing(path: &Path) -> Result<()> {
+    match fs::symlink_metadata(path).await {
+        Ok(meta) if !meta.is_dir() => fs::remove_file(path).await,
+        Ok(_) => Ok(()),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+async fn set_metadata(path: PathBuf, mode: u32, mtime: SystemTime) -> Result<()> {
+    let mtime = FileTime::from_system_time(mtime);
+    blocking(move || {
+        // Unlike the other filetime functions, this one doesn't open the
+        // file, which would block on FIFOs. There is no symlink to follow.
+        filetime::set_symlink_file_times(&path, mtime, mtime)?;
+        set_mode(&path, mode)
+    })
+    .await
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    let mut perms = std::fs::metadata(path)?.permissions();
+    perms.set_readonly(mode & 0o222 == 0);
+    std::fs::set_permissions(path, perms)
+}
+
+#[cfg(unix)]
+async fn symlink(target: &Path, path: &Path) -> Result<()> {
+    fs::symlink(target, path).await
+}
+
+#[cfg(windows)]
+async fn symlink(target: &Path, path: &Path) -> Result<()> {
+    fs::symlink_file(target, path).await
+}
+
+#[cfg(not(any(unix, windows)))]
+async fn symlink(_target: &Path, _path: &Path) -> Result<()> {
+    Err(IoError::new(
+        ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
+
+#[cfg(unix)]
+async fn mkfifo(path: &Path, mode: u32) -> Result<()> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    blocking(move || {
+        // SAFETY: `path` is a valid C string that outlives the call.
+        if unsafe { libc::mkfifo(path.as_ptr(), mode as libc::mode_t) } == 0 {
+            Ok(())
+        } else {
+            Err(IoError::last_os_error())
+        }
+    })
+    .await
+}
+
+#[cfg(not(unix))]
+async fn mkfifo(_path: &Path, _mode: u32) -> Result<()> {
+    Err(IoError::new(
+        ErrorKind::Unsupported,
+        "FIFOs are not supported on this platform",
+    ))
+}
+
+/// Runs blocking filesystem calls that tokio has no async version of.
+async fn blocking<F>(f: F) -> Result<()>
+where
+    F: FnOnce() -> Result<()> + Send + 'static,
+{
+    task::spawn_blocking(f).await.map_err(IoError::other)?
+}
@@ -3,7 +3,7 @@ use std::fmt;
 use std::io;
 use std::mem;
 
-pub use tar::Header;
+pub use tar::{EntryType, Header};
 
 /// A TAR byte stream is a series of 512-byte blocks.
 pub const BLOCK_SIZE: usize = 512;
@@ -25,9 +25,7 @@ impl Default for Block {
 
 impl fmt::Debug for Block {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let h = self.as_header();
-        if h.is_ok() {
-            let h = h.unwrap();
+        if let Ok(h) = self.as_header() {
             f.debug_struct("Block")
                 .field("is_header", &true)
                 .field("path", &h.path())
@@ -70,18 +68,75 @@ impl Block {
 
     #[inline]
     pub fn as_header(&self) -> io::Result<&Header> {
+        self.checksum_kind()?;
+        Ok(unsafe { cast(&self.bytes) })
+    }
+
+    /// Returns the checksum recorded in this block, if it can be parsed, and
+    /// the unsigned checksum of its bytes.
+    pub fn checksums(&self) -> io::Result<(u32, u32)> {
         let header: &Header = unsafe { cast(&self.bytes) };
-        let expected = header.cksum()?;
-        let actual = calc_cksum(&self.bytes);
+        Ok((header.cksum()?, calc_cksum(&self.bytes)))
+    }
+
+    /// Validates the header checksum of this block and returns how it was
+    /// computed. The unsigned sum is preferred when both match, which is the
+    /// case for headers without bytes over 0x7f.
+    pub fn checksum_kind(&self) -> io::Result<ChecksumKind> {
+        let (expected, actual) = self.checksums()?;
         if expected == actual {
-            Ok(header)
-        } else {
-            Err(io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!(
-                    "expected block to be a valid header; checksum expected = {expected}, actual = {actual};",
-                ),
-            ))
+            return Ok(ChecksumKind::Unsigned);
+        }
+        if i64::from(expected) == calc_signed_cksum(&self.bytes) {
+            return Ok(ChecksumKind::Signed);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "expected block to be a valid header; checksum expected = {expected}, actual = {actual};",
+            ),
+        ))
+    }
+}
+
+/// How the checksum of a header was computed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChecksumKind {
+    /// Summing bytes as unsigned, as POSIX requires.
+    Unsigned,
+    /// Summing bytes as signed, as some historical implementations did, like
+    /// Sun tar and early versions of GNU tar.
+    Signed,
+}
+
+/// Returns the mtime of a header in seconds since the epoch. Unlike
+/// [Header::mtime], this understands negative base-256 values, which GNU tar
+/// and star use for times before the epoch.
+pub fn header_mtime(header: &Header) -> io::Result<i64> {
+    let field = &header.as_old().mtime;
+    if field[0] == 0xff {
+        // A two's complement value, sign-extended across the whole field.
+        if field[..4].iter().all(|b| *b == 0xff) && field[4] & 0x80 != 0 {
+            return Ok(i64::from_be_bytes(field[4..].try_into().unwrap()));
+        }
+    } else if let Ok(secs) = i64::try_from(header.mtime()?) {
+        return Ok(secs);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "mtime out of range",
+    ))
+}
+
+/// Sets the mtime of a header in seconds since the epoch, in base-256 if it
+/// is negative, which [Header::set_mtime] cannot do.
+pub fn set_header_mtime(header: &mut Header, secs: i64) {
+    match u64::try_from(secs) {
+        Ok(secs) => header.set_mtime(secs),
+        Err(_) => {
+            let field = &mut header.as_old_mut().mtime;
+            field[..4].fill(0xff);
+            field[4..].copy_from_slice(&secs.to_be_bytes());
         }
     }
 }
@@ -94,6 +149,14 @@ fn calc_cksum(bytes: &[u8; BLOCK_SIZE]) -> u32 {
         + 8 * 32
 }
 
+fn calc_signed_cksum(bytes: &[u8; BLOCK_SIZE]) -> i64 {
+    bytes[..148]
+        .iter()
+        .chain(&bytes[156..])
+        .fold(0, |a, b| a + (*b as i8 as i64))
+        + 8 * 32
+}
+
 unsafe fn cast_bytes<U>(bytes: &[u8]) -> &U {
     assert_eq!(
         bytes.len(),
@@ -134,6 +197,59 @@ unsafe fn cast<T, U>(a: &T) -> &U {
 mod tests {
     use super::*;
 
+    #[test]
+    fn signed_checksum() {
+        let mut header = Header::new_ustar();
+        header.set_path("plain").unwrap();
+        header.set_cksum();
+        let block = Block::from_bytes(header.as_bytes());
+        assert_eq!(block.checksum_kind().unwrap(), ChecksumKind::Unsigned);
+
+        header.set_path("ünïcödé").unwrap();
+        header.set_cksum();
+        let block = Block::from_bytes(header.as_bytes());
+        assert_eq!(block.checksum_kind().unwrap(), ChecksumKind::Unsigned);
+
+        let signed = calc_signed_cksum(block.bytes.as_slice().try_into().unwrap());
+        let cksum = format!("{signed:06o}\0 ");
+        header.as_old_mut().cksum.copy_from_slice(cksum.as_bytes());
+        let block = Block::from_bytes(header.as_bytes());
+        assert_eq!(block.checksum_kind().unwrap(), ChecksumKind::Signed);
+        block.as_header().unwrap();
+
+        header.as_old_mut().cksum.copy_from_slice(b"000001\0 ");
+        let block = Block::from_bytes(header.as_bytes());
+        assert_eq!(
+            block.as_header().unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn mtime() {
+        let mut header = Header::new_gnu();
+        header.set_mtime(1_700_000_000);
+        assert_eq!(header_mtime(&header).unwrap(), 1_700_000_000);
+
+        // Values that overflow octal are written in base-256.
+        header.set_mtime(1 << 40);
+        assert_eq!(header.as_old().mtime[0], 0x80);
+        assert_eq!(header_mtime(&header).unwrap(), 1 << 40);
+
+        set_header_mtime(&mut header, -1);
+        assert_eq!(header.as_old().mtime, [0xff; 12]);
+        assert_eq!(header_mtime(&header).unwrap(), -1);
+        set_header_mtime(&mut header, -257);
+        assert_eq!(header_mtime(&header).unwrap(), -257);
+        set_header_mtime(&mut header, i64::MIN);
+        assert_eq!(header_mtime(&header).unwrap(), i64::MIN);
+        header.as_old_mut().mtime[4] = 0x7f;
+        header_mtime(&header).unwrap_err();
+
+        header.set_mtime(u64::MAX);
+        header_mtime(&header).unwrap_err();
+    }
+
     #[test]
     fn valid_header() {
         let buf: [u8; BLOCK_SIZE] = [
@@ -46,6 +46,15 @@ impl Buf {
         self.pos = 0;
     }
 
+    /// Moves the buffered bytes to the start of the buffer, to make as much
+    /// room as possible for further data after them.
+    #[inline]
+    pub fn compact(&mut self) {
+        self.buf.copy_within(self.pos..self.cap, 0);
+        self.cap -= self.pos;
+        self.pos = 0;
+    }
+
     /// The region of the buffer extending from the start of the buffer to the
     /// end of the written region. Use [Self::available] to write data into
     /// that region.
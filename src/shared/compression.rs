@@ -0,0 +1,121 @@
+use std::fmt;
+use std::io::{Error as IoError, ErrorKind, Result};
+
+/// The number of leading bytes needed to detect any supported compression.
+pub const MAGIC_LEN: usize = 10;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+/// The magic of the first bzip2 block, or of the end of stream for empty
+/// streams, which follows the block size digit.
+const BZIP2_BLOCK_MAGIC: &[u8] = &[0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+const BZIP2_EOS_MAGIC: &[u8] = &[0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+
+/// A compression format an archive may be wrapped in.
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compression {
+    /// A plain, uncompressed archive.
+    None,
+    /// gzip, usually `.tar.gz` or `.tgz`. Needs the `gzip` feature.
+    Gzip,
+    /// Zstandard, usually `.tar.zst`. Needs the `zstd` feature.
+    Zstd,
+    /// xz, usually `.tar.xz`. Needs the `xz` feature.
+    Xz,
+    /// bzip2, usually `.tar.bz2`. Needs the `bzip2` feature.
+    Bzip2,
+}
+
+impl Compression {
+    /// Detects the compression of a stream from its first bytes, which
+    /// should be at least 10 bytes long unless the stream is shorter.
+    pub fn detect(magic: &[u8]) -> Self {
+        if magic.starts_with(GZIP_MAGIC) {
+            Self::Gzip
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Self::Zstd
+        } else if magic.starts_with(XZ_MAGIC) {
+            Self::Xz
+        } else if is_bzip2(magic) {
+            Self::Bzip2
+        } else {
+            Self::None
+        }
+    }
+
+    /// Fails with [ErrorKind::Unsupported] if the feature for this
+    /// compression is not enabled.
+    pub(crate) fn check_enabled(self) -> Result<Self> {
+        let enabled = match self {
+            Self::None => true,
+            Self::Gzip => cfg!(feature = "gzip"),
+            Self::Zstd => cfg!(feature = "zstd"),
+            Self::Xz => cfg!(feature = "xz"),
+            Self::Bzip2 => cfg!(feature = "bzip2"),
+        };
+        if enabled {
+            Ok(self)
+        } else {
+            Err(IoError::new(
+                ErrorKind::Unsupported,
+                format!("{self} compression is not enabled"),
+            ))
+        }
+    }
+}
+
+impl fmt::Display for Compression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => "no",
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Xz => "xz",
+            Self::Bzip2 => "bzip2",
+        }
+        .fmt(f)
+    }
+}
+
+/// bzip2's magic is plain ASCII, so the block magic after it is checked too
+/// to not mistake archives whose first path starts with "BZh".
+fn is_bzip2(magic: &[u8]) -> bool {
+    let Some(rest) = magic.strip_prefix(BZIP2_MAGIC) else {
+        return false;
+    };
+    match rest.split_first() {
+        Some((level, rest)) => {
+            (b'1'..=b'9').contains(level)
+                && (rest.starts_with(BZIP2_BLOCK_MAGIC) || rest.starts_with(BZIP2_EOS_MAGIC))
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect() {
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 8, 0]), Compression::Gzip);
+        assert_eq!(
+            Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd, 0x24]),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::detect(b"\xfd7zXZ\0\0\x04"), Compression::Xz);
+        assert_eq!(Compression::detect(b"BZh91AY&SY\x01"), Compression::Bzip2);
+        assert_eq!(
+            Compression::detect(b"BZh9\x17\x72\x45\x38\x50\x90"),
+            Compression::Bzip2
+        );
+        assert_eq!(Compression::detect(b"BZh9.txt\0\0\0"), Compression::None);
+        assert_eq!(Compression::detect(b"hello.txt\0"), Compression::None);
+        assert_eq!(Compression::detect(&[0x1f]), Compression::None);
+        assert_eq!(Compression::detect(&[]), Compression::None);
+    }
+}
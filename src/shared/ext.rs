@@ -0,0 +1,15 @@
+use super::pax::PaxAttributes;
+use super::sparse::Sparse;
+
+/// Extensions that apply to a single entry, read from or to be written as
+/// extension entries preceding it.
+#[derive(Debug, Default)]
+pub struct EntryExtensions {
+    pub pax: PaxAttributes,
+    /// Whether the entry has a PAX extended header of its own, rather than
+    /// only global attributes.
+    pub local_pax: bool,
+    pub long_name: Option<Box<[u8]>>,
+    pub long_link: Option<Box<[u8]>>,
+    pub sparse: Option<Sparse>,
+}
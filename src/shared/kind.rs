@@ -0,0 +1,174 @@
+use std::borrow::Cow;
+use std::io::{Error as IoError, ErrorKind, Result};
+use std::str;
+
+use super::block::Header;
+
+/// The trailer of star headers, at the end of the block.
+const STAR_TRAILER: &[u8] = b"tar\0";
+
+/// Offsets of the fields star places where ustar has the tail of its prefix.
+const STAR_PREFIX: usize = 345;
+const STAR_PREFIX_LEN: usize = 131;
+const STAR_ATIME: usize = 476;
+const STAR_CTIME: usize = 488;
+const STAR_TRAILER_OFFSET: usize = 508;
+
+/// The format of an entry, as far as can be told from its header and the
+/// extension entries preceding it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HeaderKind {
+    /// The original Unix V7 format, with no magic.
+    V7,
+    /// The POSIX.1-1988 ustar format.
+    Ustar,
+    /// The GNU tar format.
+    Gnu,
+    /// The format of Jörg Schilling's star, a ustar header with atime and
+    /// ctime fields in the prefix.
+    Star,
+    /// The POSIX.1-2001 pax format: a ustar header preceded by extended
+    /// headers.
+    Pax,
+}
+
+impl HeaderKind {
+    /// Detects the format of a header from its magic. This cannot tell PAX
+    /// entries apart, since they have plain ustar headers.
+    pub fn detect(header: &Header) -> Self {
+        if header.as_ustar().is_some() {
+            if &header.as_bytes()[STAR_TRAILER_OFFSET..] == STAR_TRAILER {
+                Self::Star
+            } else {
+                Self::Ustar
+            }
+        } else if header.as_gnu().is_some() {
+            Self::Gnu
+        } else {
+            Self::V7
+        }
+    }
+}
+
+/// Returns the path of a star header, whose prefix is shorter than ustar's.
+pub fn star_path(header: &Header) -> Cow<'_, [u8]> {
+    let bytes = header.as_bytes();
+    let prefix = truncate(&bytes[STAR_PREFIX..STAR_PREFIX + STAR_PREFIX_LEN]);
+    let name = truncate(&header.as_old().name);
+    if prefix.is_empty() {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned([prefix, b"/", name].concat())
+    }
+}
+
+/// Returns the atime of a star header, if set.
+pub fn star_atime(header: &Header) -> Result<Option<u64>> {
+    numeric(&header.as_bytes()[STAR_ATIME..STAR_ATIME + 12])
+}
+
+/// Returns the ctime of a star header, if set.
+pub fn star_ctime(header: &Header) -> Result<Option<u64>> {
+    numeric(&header.as_bytes()[STAR_CTIME..STAR_CTIME + 12])
+}
+
+/// Returns the atime of a GNU header, if set.
+pub fn gnu_atime(header: &Header) -> Result<Option<u64>> {
+    header.as_gnu().map_or(Ok(None), |h| numeric(&h.atime))
+}
+
+/// Returns the ctime of a GNU header, if set.
+pub fn gnu_ctime(header: &Header) -> Result<Option<u64>> {
+    header.as_gnu().map_or(Ok(None), |h| numeric(&h.ctime))
+}
+
+/// Parses an octal or base-256 numeric field. Empty fields, which are
+/// common for optional fields such as atime, are [None].
+///
+/// Negative base-256 values and those too wide for a [u64] are invalid.
+fn numeric(field: &[u8]) -> Result<Option<u64>> {
+    if field[0] & 0x80 != 0 {
+        // The low 7 bits of the first byte and any bytes before the last
+        // 8 hold the high-order bits, sign included.
+        let (high, low) = field.split_at(field.len() - 8);
+        if high[0] & 0x7f != 0 || high[1..].iter().any(|b| *b != 0) {
+            return Err(invalid_field(field));
+        }
+        return Ok(Some(u64::from_be_bytes(low.try_into().unwrap())));
+    }
+    let value = str::from_utf8(truncate(field))
+        .ok()
+        .map(|s| s.trim_matches(' '))
+        .ok_or_else(|| invalid_field(field))?;
+    if value.is_empty() {
+        return Ok(None);
+    }
+    u64::from_str_radix(value, 8)
+        .map(Some)
+        .map_err(|_| invalid_field(field))
+}
+
+fn truncate(field: &[u8]) -> &[u8] {
+    let len = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    &field[..len]
+}
+
+fn invalid_field(field: &[u8]) -> IoError {
+    IoError::new(
+        ErrorKind::InvalidData,
+        format!(
+            "invalid numeric field: {}",
+            String::from_utf8_lossy(truncate(field))
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shared::test::make_star_header;
+
+    use super::*;
+
+    #[test]
+    fn detect() {
+        assert_eq!(HeaderKind::detect(&Header::new_ustar()), HeaderKind::Ustar);
+        assert_eq!(HeaderKind::detect(&Header::new_gnu()), HeaderKind::Gnu);
+        assert_eq!(HeaderKind::detect(&Header::new_old()), HeaderKind::V7);
+
+        let header = make_star_header("dir/file", 1, 2);
+        assert_eq!(HeaderKind::detect(&header), HeaderKind::Star);
+        assert_eq!(star_path(&header).as_ref(), b"dir/file");
+        assert_eq!(star_atime(&header).unwrap(), Some(1));
+        assert_eq!(star_ctime(&header).unwrap(), Some(2));
+
+        // The ustar interpretation mistakes the times for part of a prefix
+        // that fills its field.
+        let path = "p".repeat(STAR_PREFIX_LEN) + "/file";
+        let header = make_star_header(&path, 1, 2);
+        assert_eq!(star_path(&header).as_ref(), path.as_bytes());
+        assert_ne!(header.path_bytes().as_ref(), path.as_bytes());
+    }
+
+    #[test]
+    fn numeric_fields() {
+        assert_eq!(numeric(b"00000000017\0").unwrap(), Some(0o17));
+        assert_eq!(numeric(b"     17 \0\0\0\0").unwrap(), Some(0o17));
+        assert_eq!(numeric(&[0; 12]).unwrap(), None);
+        let mut field = [0u8; 12];
+        field[0] = 0x80;
+        field[4] = 0xff;
+        field[11] = 1;
+        assert_eq!(numeric(&field).unwrap(), Some(0xff00_0000_0000_0001));
+
+        // Too wide for a u64.
+        field[3] = 1;
+        numeric(&field).unwrap_err();
+        field[3] = 0;
+        field[0] = 0x81;
+        numeric(&field).unwrap_err();
+
+        // Negative.
+        numeric(&[0xff; 12]).unwrap_err();
+        numeric(b"0000000009\0\0").unwrap_err();
+    }
+}
@@ -1,6 +1,13 @@
 pub mod block;
 pub mod buffer;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod ext;
+pub mod kind;
+pub mod pax;
+pub mod poison;
 pub mod slices;
+pub mod sparse;
 pub mod state;
 
 #[cfg(test)]
@@ -0,0 +1,355 @@
+use std::io::{Error as IoError, ErrorKind, Result};
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tar::PaxExtensions;
+
+const PATH: &str = "path";
+const LINKPATH: &str = "linkpath";
+const SIZE: &str = "size";
+const UID: &str = "uid";
+const GID: &str = "gid";
+const UNAME: &str = "uname";
+const GNAME: &str = "gname";
+const MTIME: &str = "mtime";
+const ATIME: &str = "atime";
+const CTIME: &str = "ctime";
+
+type Record = (Box<[u8]>, Box<[u8]>);
+
+/// A set of attributes read from PAX extended headers.
+///
+/// Records are kept in the order they were first seen; a record for a key
+/// that is already present replaces the existing value.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaxAttributes {
+    records: Vec<Record>,
+}
+
+impl PaxAttributes {
+    /// Parses the data of a PAX extended header into a new attribute set.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut attrs = Self::default();
+        attrs.extend_from_bytes(data)?;
+        Ok(attrs)
+    }
+
+    /// Parses the data of a PAX extended header and merges its records into
+    /// this set, overriding any existing values.
+    pub(crate) fn extend_from_bytes(&mut self, data: &[u8]) -> Result<()> {
+        // Validate all records before touching our own so that a malformed
+        // header leaves us unmodified.
+        let records = PaxExtensions::new(data).collect::<Result<Vec<_>>>()?;
+        for record in records {
+            self.insert(record.key_bytes(), record.value_bytes());
+        }
+        Ok(())
+    }
+
+    /// Merges the records of another set into this one, overriding any
+    /// existing values.
+    pub(crate) fn merge(&mut self, other: &Self) {
+        for (key, value) in other.iter() {
+            self.insert(key, value);
+        }
+    }
+
+    /// Sets the value of the given key, replacing any existing value.
+    pub fn insert(&mut self, key: &[u8], value: &[u8]) {
+        match self.records.iter_mut().find(|(k, _)| **k == *key) {
+            Some((_, v)) => *v = value.into(),
+            None => self.records.push((key.into(), value.into())),
+        }
+    }
+
+    /// Removes the given key, returning whether it was present.
+    pub fn remove(&mut self, key: &[u8]) -> bool {
+        let len = self.records.len();
+        self.records.retain(|(k, _)| **k != *key);
+        self.records.len() != len
+    }
+
+    /// Encodes this set as the data of a PAX extended header.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (key, value) in self.iter() {
+            // The length prefix counts the whole record including itself,
+            // so it has to account for its own digits.
+            let rest = key.len() + value.len() + 3; // ' ', '=' and '\n'
+            let mut len = rest + 1;
+            while rest + digits(len) != len {
+                len = rest + digits(len);
+            }
+            data.extend_from_slice(len.to_string().as_bytes());
+            data.push(b' ');
+            data.extend_from_slice(key);
+            data.push(b'=');
+            data.extend_from_slice(value);
+            data.push(b'\n');
+        }
+        data
+    }
+
+    /// Returns the number of records in this set.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Returns whether this set has no records.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Returns an iterator over the key/value pairs in this set.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8])> {
+        self.records.iter().map(|(k, v)| (&**k, &**v))
+    }
+
+    /// Returns the raw value of the given key.
+    ///
+    /// An empty value is returned as is; PAX uses those to unset a value
+    /// that would otherwise apply.
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.records
+            .iter()
+            .find(|(k, _)| **k == *key.as_bytes())
+            .map(|(_, v)| &**v)
+    }
+
+    /// Returns the `path` attribute.
+    #[inline]
+    pub fn path(&self) -> Option<&[u8]> {
+        self.get_non_empty(PATH)
+    }
+
+    /// Returns the `linkpath` attribute.
+    #[inline]
+    pub fn link_path(&self) -> Option<&[u8]> {
+        self.get_non_empty(LINKPATH)
+    }
+
+    /// Returns the `size` attribute.
+    #[inline]
+    pub fn size(&self) -> Result<Option<u64>> {
+        self.get_u64(SIZE)
+    }
+
+    /// Returns the `uid` attribute.
+    #[inline]
+    pub fn uid(&self) -> Result<Option<u64>> {
+        self.get_u64(UID)
+    }
+
+    /// Returns the `gid` attribute.
+    #[inline]
+    pub fn gid(&self) -> Result<Option<u64>> {
+        self.get_u64(GID)
+    }
+
+    /// Returns the `uname` attribute.
+    #[inline]
+    pub fn username(&self) -> Option<&[u8]> {
+        self.get_non_empty(UNAME)
+    }
+
+    /// Returns the `gname` attribute.
+    #[inline]
+    pub fn groupname(&self) -> Option<&[u8]> {
+        self.get_non_empty(GNAME)
+    }
+
+    /// Returns the `mtime` attribute, with sub-second precision if present.
+    #[inline]
+    pub fn mtime(&self) -> Result<Option<SystemTime>> {
+        self.get_time(MTIME)
+    }
+
+    /// Returns the `atime` attribute, with sub-second precision if present.
+    #[inline]
+    pub fn atime(&self) -> Result<Option<SystemTime>> {
+        self.get_time(ATIME)
+    }
+
+    /// Returns the `ctime` attribute, with sub-second precision if present.
+    #[inline]
+    pub fn ctime(&self) -> Result<Option<SystemTime>> {
+        self.get_time(CTIME)
+    }
+
+    fn get_non_empty(&self, key: &str) -> Option<&[u8]> {
+        self.get(key).filter(|v| !v.is_empty())
+    }
+
+    fn get_u64(&self, key: &str) -> Result<Option<u64>> {
+        let Some(value) = self.get_non_empty(key) else {
+            return Ok(None);
+        };
+        str::from_utf8(value)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .map(Some)
+            .ok_or_else(|| invalid_value(key, value))
+    }
+
+    fn get_time(&self, key: &str) -> Result<Option<SystemTime>> {
+        let Some(value) = self.get_non_empty(key) else {
+            return Ok(None);
+        };
+        parse_time(value)
+            .map(Some)
+            .ok_or_else(|| invalid_value(key, value))
+    }
+}
+
+/// Formats a time as a PAX time value, with as much precision as needed.
+pub(crate) fn format_time(time: SystemTime) -> String {
+    let (sign, duration) = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => ("", duration),
+        Err(err) => ("-", err.duration()),
+    };
+    let secs = duration.as_secs();
+    match duration.subsec_nanos() {
+        0 => format!("{sign}{secs}"),
+        nanos => {
+            let frac = format!("{nanos:09}");
+            format!("{sign}{secs}.{}", frac.trim_end_matches('0'))
+        }
+    }
+}
+
+fn digits(n: usize) -> usize {
+    n.checked_ilog10().unwrap_or(0) as usize + 1
+}
+
+/// Parses a PAX time value of the form `[-]seconds[.fraction]`.
+fn parse_time(value: &[u8]) -> Option<SystemTime> {
+    let value = str::from_utf8(value).ok()?;
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let (secs, frac) = value.split_once('.').unwrap_or((value, ""));
+
+    let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    if secs.is_empty() || !is_digits(secs) || !is_digits(frac) {
+        return None;
+    }
+
+    let secs: u64 = secs.parse().ok()?;
+    // Anything past nanosecond precision is truncated.
+    let nanos = frac
+        .bytes()
+        .chain(std::iter::repeat(b'0'))
+        .take(9)
+        .fold(0u32, |acc, b| acc * 10 + (b - b'0') as u32);
+    let duration = Duration::new(secs, nanos);
+
+    if negative {
+        UNIX_EPOCH.checked_sub(duration)
+    } else {
+        UNIX_EPOCH.checked_add(duration)
+    }
+}
+
+fn invalid_value(key: &str, value: &[u8]) -> IoError {
+    IoError::new(
+        ErrorKind::InvalidData,
+        format!(
+            "invalid value for pax attribute {key}: {}",
+            String::from_utf8_lossy(value)
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shared::test::make_pax_data;
+
+    use super::*;
+
+    fn record(key: &str, value: &str) -> Vec<u8> {
+        make_pax_data(&[(key, value)])
+    }
+
+    #[test]
+    fn parse() {
+        let data = [
+            record("path", "some/long/path"),
+            record("size", "12345"),
+            record("mtime", "1700000000.25"),
+            record("uname", ""),
+            record("path", "other/path"),
+        ]
+        .concat();
+
+        let attrs = PaxAttributes::parse(&data).unwrap();
+        assert_eq!(attrs.len(), 4);
+        assert_eq!(attrs.path(), Some(&b"other/path"[..]));
+        assert_eq!(attrs.size().unwrap(), Some(12345));
+        assert_eq!(attrs.uid().unwrap(), None);
+        assert_eq!(attrs.username(), None);
+        assert_eq!(attrs.get("uname"), Some(&b""[..]));
+        assert_eq!(
+            attrs.mtime().unwrap(),
+            Some(UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000))
+        );
+    }
+
+    #[test]
+    fn parse_malformed() {
+        let data = b"99 path=foo\n";
+        let err = PaxAttributes::parse(data).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::Other);
+
+        let attrs = PaxAttributes::parse(&record("size", "-1")).unwrap();
+        assert_eq!(attrs.size().unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn encode() {
+        let mut attrs = PaxAttributes::default();
+        attrs.insert(b"path", "a".repeat(91).as_bytes());
+        attrs.insert(b"mtime", b"1.5");
+        let data = attrs.to_bytes();
+        assert_eq!(&data[..4], b"101 ");
+        assert_eq!(PaxAttributes::parse(&data).unwrap(), attrs);
+
+        for len in 0..1100 {
+            let mut attrs = PaxAttributes::default();
+            attrs.insert(b"comment", "x".repeat(len).as_bytes());
+            assert_eq!(PaxAttributes::parse(&attrs.to_bytes()).unwrap(), attrs);
+        }
+    }
+
+    #[test]
+    fn time() {
+        for time in [
+            UNIX_EPOCH,
+            UNIX_EPOCH + Duration::new(1_700_000_000, 500_000_000),
+            UNIX_EPOCH + Duration::new(1, 1),
+            UNIX_EPOCH - Duration::new(12, 250_000_000),
+        ] {
+            assert_eq!(parse_time(format_time(time).as_bytes()), Some(time));
+        }
+        assert_eq!(
+            format_time(UNIX_EPOCH + Duration::new(1, 500_000_000)),
+            "1.5"
+        );
+
+        assert_eq!(parse_time(b"0"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse_time(b"12.000000001999"),
+            Some(UNIX_EPOCH + Duration::new(12, 1))
+        );
+        assert_eq!(
+            parse_time(b"-1.5"),
+            Some(UNIX_EPOCH - Duration::new(1, 500_000_000))
+        );
+        assert_eq!(parse_time(b""), None);
+        assert_eq!(parse_time(b".5"), None);
+        assert_eq!(parse_time(b"1.-5"), None);
+        assert_eq!(parse_time(b"1e3"), None);
+    }
+}
@@ -0,0 +1,56 @@
+use std::io::{Error as IoError, Result};
+use std::pin::Pin;
+
+use crate::{Archive, ArchiveError, ReadError, WriteError};
+
+/// The misuse that left an archive unusable. Every call after it fails with
+/// an error whose source is this, or with this again if it's an error in
+/// the archive itself.
+#[derive(Debug, Clone)]
+pub enum Poison {
+    Read(ReadError),
+    Write(WriteError),
+    Archive(ArchiveError),
+}
+
+impl From<ReadError> for Poison {
+    #[inline]
+    fn from(value: ReadError) -> Self {
+        Self::Read(value)
+    }
+}
+
+impl From<WriteError> for Poison {
+    #[inline]
+    fn from(value: WriteError) -> Self {
+        Self::Write(value)
+    }
+}
+
+impl From<ArchiveError> for Poison {
+    #[inline]
+    fn from(value: ArchiveError) -> Self {
+        Self::Archive(value)
+    }
+}
+
+impl<T> Archive<T> {
+    /// Leaves the archive poisoned by `cause`, which is returned as is.
+    pub(crate) fn poison<E>(self: Pin<&mut Self>, cause: E) -> IoError
+    where
+        E: Into<Poison> + Into<IoError> + Clone,
+    {
+        *self.project().poison = Some(cause.clone().into());
+        cause.into()
+    }
+
+    /// Fails if the archive has been poisoned.
+    pub(crate) fn check_poison(&self) -> Result<()> {
+        match &self.poison {
+            None => Ok(()),
+            Some(Poison::Read(cause)) => ReadError::Poisoned(Box::new(cause.clone())).into(),
+            Some(Poison::Write(cause)) => WriteError::Poisoned(Box::new(cause.clone())).into(),
+            Some(Poison::Archive(cause)) => cause.clone().into(),
+        }
+    }
+}
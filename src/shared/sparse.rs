@@ -0,0 +1,466 @@
+use std::io::{Error as IoError, ErrorKind, Result};
+use std::str;
+
+use tar::{GnuSparseHeader, PaxExtensions};
+
+use super::block::{BLOCK_SIZE, Header};
+use super::pax::PaxAttributes;
+
+const SPARSE_MAJOR: &str = "GNU.sparse.major";
+const SPARSE_MINOR: &str = "GNU.sparse.minor";
+const SPARSE_NAME: &str = "GNU.sparse.name";
+const SPARSE_SIZE: &str = "GNU.sparse.size";
+const SPARSE_REALSIZE: &str = "GNU.sparse.realsize";
+const SPARSE_MAP: &str = "GNU.sparse.map";
+const SPARSE_OFFSET: &[u8] = b"GNU.sparse.offset";
+const SPARSE_NUMBYTES: &[u8] = b"GNU.sparse.numbytes";
+
+/// The number of sparse headers in an extension block of an old GNU sparse
+/// entry. The `isextended` flag follows them.
+const GNU_EXT_SPARSE_HEADERS_COUNT: usize = 21;
+
+/// A buffer of zeros to read holes from.
+static ZEROS: [u8; 16 * BLOCK_SIZE] = [0u8; 16 * BLOCK_SIZE];
+
+/// A region of data in a sparse file. Anything not covered by a region is a
+/// hole and reads as zeros.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SparseRegion {
+    /// The offset of the region in the file.
+    pub offset: u64,
+    /// The length of the region.
+    pub len: u64,
+}
+
+impl SparseRegion {
+    #[inline]
+    fn end(&self) -> u64 {
+        self.offset + self.len
+    }
+}
+
+/// The layout of a sparse entry and the position of a reader within it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Sparse {
+    /// The data regions of the file, in order.
+    pub regions: Vec<SparseRegion>,
+
+    /// The logical size of the file, including holes.
+    pub size: u64,
+
+    /// The real path of the file, for PAX sparse entries whose header path
+    /// is made up.
+    pub name: Option<Box<[u8]>>,
+
+    /// The number of bytes the sparse map occupies in the archive, ahead of
+    /// the entry data.
+    pub map_len: u64,
+
+    /// The current position in the file.
+    pos: u64,
+
+    /// The index of the first region that does not end before `pos`.
+    index: usize,
+}
+
+/// What comes next when reading a sparse file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chunk {
+    /// This many bytes of data to be read from the archive.
+    Data(u64),
+    /// This many bytes of zeros.
+    Hole(u64),
+    /// The end of the file.
+    End,
+}
+
+impl Sparse {
+    /// Creates the layout of a sparse file with the given regions and size.
+    #[inline]
+    pub fn new(regions: Vec<SparseRegion>, size: u64, name: Option<Box<[u8]>>) -> Self {
+        Self {
+            regions,
+            size,
+            name,
+            ..Self::default()
+        }
+    }
+
+    /// Reads the sparse map stored in an old GNU sparse header. Returns
+    /// whether extension blocks with more regions follow the header.
+    pub fn from_gnu(header: &Header) -> Result<Option<(Self, bool)>> {
+        if !header.entry_type().is_gnu_sparse() {
+            return Ok(None);
+        }
+        let Some(gnu) = header.as_gnu() else {
+            return Err(invalid_map("sparse header is not a gnu header"));
+        };
+        let mut sparse = Self {
+            size: gnu.real_size()?,
+            ..Self::default()
+        };
+        sparse.extend_from_headers(&gnu.sparse)?;
+        Ok(Some((sparse, gnu.is_extended())))
+    }
+
+    /// Reads the regions of an old GNU sparse extension block. Returns
+    /// whether yet another extension block follows.
+    pub fn extend_from_gnu_block(&mut self, block: &[u8]) -> Result<bool> {
+        debug_assert_eq!(block.len(), BLOCK_SIZE);
+        let headers = block
+            .chunks_exact(24)
+            .take(GNU_EXT_SPARSE_HEADERS_COUNT)
+            .map(|chunk| GnuSparseHeader {
+                offset: chunk[..12].try_into().unwrap(),
+                numbytes: chunk[12..].try_into().unwrap(),
+            })
+            .collect::<Vec<_>>();
+        self.extend_from_headers(&headers)?;
+        self.map_len += BLOCK_SIZE as u64;
+        Ok(block[GNU_EXT_SPARSE_HEADERS_COUNT * 24] == 1)
+    }
+
+    fn extend_from_headers(&mut self, headers: &[GnuSparseHeader]) -> Result<()> {
+        for header in headers.iter().take_while(|h| !h.is_empty()) {
+            self.regions.push(SparseRegion {
+                offset: header.offset()?,
+                len: header.length()?,
+            });
+        }
+        Ok(())
+    }
+
+    /// Reads the sparse map of a PAX sparse entry, in any of the formats
+    /// GNU tar has used: 0.0 and 0.1 keep it in the extended header, and 1.0
+    /// at the start of the entry data. `regions` are those of the repeated
+    /// 0.0 `GNU.sparse.offset` and `GNU.sparse.numbytes` records, which
+    /// [PaxAttributes] cannot keep.
+    ///
+    /// Returns whether the map still has to be read from the entry data.
+    pub fn from_pax(
+        attrs: &PaxAttributes,
+        regions: Vec<SparseRegion>,
+    ) -> Result<Option<(Self, bool)>> {
+        let name = attrs
+            .get(SPARSE_NAME)
+            .filter(|name| !name.is_empty())
+            .map(Box::from);
+
+        if attrs.get(SPARSE_MAJOR) == Some(b"1") && attrs.get(SPARSE_MINOR) == Some(b"0") {
+            let sparse = Self {
+                size: get_u64(attrs, SPARSE_REALSIZE)?
+                    .ok_or_else(|| invalid_map("missing GNU.sparse.realsize"))?,
+                name,
+                ..Self::default()
+            };
+            return Ok(Some((sparse, true)));
+        }
+
+        let regions = match attrs.get(SPARSE_MAP) {
+            Some(map) => parse_regions(map.split(|b| *b == b','))?,
+            None if !regions.is_empty() => regions,
+            None => return Ok(None),
+        };
+        let sparse = Self {
+            regions,
+            size: get_u64(attrs, SPARSE_SIZE)?
+                .ok_or_else(|| invalid_map("missing GNU.sparse.size"))?,
+            name,
+            ..Self::default()
+        };
+        Ok(Some((sparse, false)))
+    }
+
+    /// Reads the 0.0 sparse regions from the data of a PAX extended header.
+    pub fn pax_regions(data: &[u8]) -> Result<Vec<SparseRegion>> {
+        let mut offset = None;
+        let mut regions = Vec::new();
+        for record in PaxExtensions::new(data) {
+            let record = record?;
+            match (record.key_bytes(), offset) {
+                (SPARSE_OFFSET, None) => offset = Some(record.value_bytes()),
+                (SPARSE_NUMBYTES, Some(value)) => {
+                    regions.extend(parse_regions([value, record.value_bytes()])?);
+                    offset = None;
+                }
+                (SPARSE_OFFSET | SPARSE_NUMBYTES, _) => {
+                    return Err(invalid_map("unpaired sparse offset and numbytes"));
+                }
+                _ => {}
+            }
+        }
+        if offset.is_some() {
+            return Err(invalid_map("unpaired sparse offset and numbytes"));
+        }
+        Ok(regions)
+    }
+
+    /// Tries to read the 1.0 sparse map from the start of the entry data,
+    /// which is a series of decimal numbers each followed by a newline: the
+    /// number of regions and then the offset and length of each. The map is
+    /// padded to a block boundary, so `data` must be whole blocks.
+    ///
+    /// Returns whether the map is complete.
+    pub fn extend_from_pax_map(&mut self, data: &[u8]) -> Result<bool> {
+        debug_assert_eq!(data.len() % BLOCK_SIZE, 0);
+        let mut lines = data.split(|b| *b == b'\n');
+        let count = match lines.next() {
+            Some(line) if line.len() < data.len() => parse_number(line)?,
+            _ => return Ok(false),
+        };
+        // Every line but the last one, which is not terminated, is complete.
+        let complete = data.iter().filter(|b| **b == b'\n').count() as u64 - 1;
+        if complete < count.saturating_mul(2) {
+            return Ok(false);
+        }
+        self.regions = parse_regions(lines.take(count as usize * 2))?;
+        self.map_len = data.len() as u64;
+        Ok(true)
+    }
+
+    /// Records the PAX 1.0 sparse attributes of this file.
+    pub fn to_pax_attributes(&self, attrs: &mut PaxAttributes) {
+        attrs.insert(SPARSE_MAJOR.as_bytes(), b"1");
+        attrs.insert(SPARSE_MINOR.as_bytes(), b"0");
+        if let Some(name) = &self.name {
+            attrs.insert(SPARSE_NAME.as_bytes(), name);
+        }
+        attrs.insert(SPARSE_REALSIZE.as_bytes(), self.size.to_string().as_bytes());
+    }
+
+    /// Encodes the PAX 1.0 sparse map of this file, padded to a block
+    /// boundary, and records its length.
+    pub fn encode_pax_map(&mut self) -> Vec<u8> {
+        let mut map = format!("{}\n", self.regions.len());
+        for region in &self.regions {
+            map.push_str(&format!("{}\n{}\n", region.offset, region.len));
+        }
+        let mut map = map.into_bytes();
+        map.resize(map.len().next_multiple_of(BLOCK_SIZE), 0);
+        self.map_len = map.len() as u64;
+        map
+    }
+
+    /// Checks that the regions are in order, do not overlap, are within the
+    /// file and add up to the length of data in the archive.
+    pub fn validate(&self, data_len: u64) -> Result<()> {
+        let mut end = 0;
+        let mut total = 0u64;
+        for region in &self.regions {
+            if region.offset < end {
+                return Err(invalid_map("sparse regions overlap or are out of order"));
+            }
+            end = region
+                .offset
+                .checked_add(region.len)
+                .filter(|end| *end <= self.size)
+                .ok_or_else(|| invalid_map("sparse region is past the end of the file"))?;
+            total += region.len;
+        }
+        if total != data_len {
+            return Err(invalid_map("sparse regions do not match entry data"));
+        }
+        Ok(())
+    }
+
+    /// Returns what comes next in the file.
+    pub fn chunk(&self) -> Chunk {
+        if self.pos >= self.size {
+            return Chunk::End;
+        }
+        match self.regions.get(self.index) {
+            Some(region) if region.offset <= self.pos => Chunk::Data(region.end() - self.pos),
+            Some(region) => Chunk::Hole(region.offset - self.pos),
+            None => Chunk::Hole(self.size - self.pos),
+        }
+    }
+
+    /// Advances the position in the file by `amt` bytes, which must not be
+    /// more than the current [chunk][Self::chunk].
+    pub fn advance(&mut self, amt: usize) {
+        self.pos += amt as u64;
+        while self
+            .regions
+            .get(self.index)
+            .is_some_and(|r| r.end() <= self.pos)
+        {
+            self.index += 1;
+        }
+    }
+
+    /// Returns a buffer of zeros of at most the given length.
+    #[inline]
+    pub fn zeros(len: u64) -> &'static [u8] {
+        &ZEROS[..ZEROS.len().min(len as usize)]
+    }
+}
+
+fn get_u64(attrs: &PaxAttributes, key: &str) -> Result<Option<u64>> {
+    attrs.get(key).map(parse_number).transpose()
+}
+
+fn parse_number(value: &[u8]) -> Result<u64> {
+    str::from_utf8(value)
+        .ok()
+        .filter(|s| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid_map("invalid number in sparse map"))
+}
+
+fn parse_regions<'a>(values: impl IntoIterator<Item = &'a [u8]>) -> Result<Vec<SparseRegion>> {
+    let mut values = values.into_iter();
+    let mut regions = Vec::new();
+    while let Some(offset) = values.next() {
+        let len = values
+            .next()
+            .ok_or_else(|| invalid_map("sparse map has an odd number of values"))?;
+        regions.push(SparseRegion {
+            offset: parse_number(offset)?,
+            len: parse_number(len)?,
+        });
+    }
+    Ok(regions)
+}
+
+fn invalid_map(msg: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::shared::test::make_pax_data;
+
+    use super::*;
+
+    fn region(offset: u64, len: u64) -> SparseRegion {
+        SparseRegion { offset, len }
+    }
+
+    #[test]
+    fn chunks() {
+        let mut sparse = Sparse {
+            regions: vec![region(0, 10), region(100, 5), region(200, 0)],
+            size: 200,
+            ..Sparse::default()
+        };
+        sparse.validate(15).unwrap();
+
+        let mut chunks = Vec::new();
+        loop {
+            let chunk = sparse.chunk();
+            chunks.push(chunk);
+            match chunk {
+                Chunk::Data(n) | Chunk::Hole(n) => sparse.advance(n as usize),
+                Chunk::End => break,
+            }
+        }
+        assert_eq!(
+            chunks,
+            [
+                Chunk::Data(10),
+                Chunk::Hole(90),
+                Chunk::Data(5),
+                Chunk::Hole(95),
+                Chunk::End,
+            ]
+        );
+
+        let mut sparse = Sparse {
+            regions: vec![region(10, 10)],
+            size: 20,
+            ..Sparse::default()
+        };
+        sparse.advance(4);
+        assert_eq!(sparse.chunk(), Chunk::Hole(6));
+        sparse.advance(10);
+        assert_eq!(sparse.chunk(), Chunk::Data(6));
+        sparse.advance(6);
+        assert_eq!(sparse.chunk(), Chunk::End);
+    }
+
+    #[test]
+    fn validate() {
+        let sparse = |regions: Vec<SparseRegion>| Sparse {
+            regions,
+            size: 100,
+            ..Sparse::default()
+        };
+        sparse(vec![]).validate(0).unwrap();
+        sparse(vec![region(0, 100)]).validate(100).unwrap();
+        sparse(vec![region(0, 10)]).validate(11).unwrap_err();
+        sparse(vec![region(0, 101)]).validate(101).unwrap_err();
+        sparse(vec![region(10, 10), region(15, 10)])
+            .validate(20)
+            .unwrap_err();
+        sparse(vec![region(u64::MAX, 1)]).validate(1).unwrap_err();
+    }
+
+    #[test]
+    fn pax_maps() {
+        let data = make_pax_data(&[
+            ("GNU.sparse.size", "1000"),
+            ("GNU.sparse.numblocks", "2"),
+            ("GNU.sparse.offset", "0"),
+            ("GNU.sparse.numbytes", "10"),
+            ("GNU.sparse.offset", "500"),
+            ("GNU.sparse.numbytes", "20"),
+        ]);
+        let regions = Sparse::pax_regions(&data).unwrap();
+        assert_eq!(regions, [region(0, 10), region(500, 20)]);
+        let attrs = PaxAttributes::parse(&data).unwrap();
+        let (sparse, in_data) = Sparse::from_pax(&attrs, regions).unwrap().unwrap();
+        assert!(!in_data);
+        assert_eq!(sparse.size, 1000);
+
+        let data = make_pax_data(&[("GNU.sparse.offset", "0")]);
+        Sparse::pax_regions(&data).unwrap_err();
+
+        let data = make_pax_data(&[
+            ("GNU.sparse.size", "1000"),
+            ("GNU.sparse.map", "0,10,500,20"),
+            ("GNU.sparse.name", "real/name"),
+        ]);
+        let attrs = PaxAttributes::parse(&data).unwrap();
+        let (sparse, _) = Sparse::from_pax(&attrs, Vec::new()).unwrap().unwrap();
+        assert_eq!(sparse.regions, [region(0, 10), region(500, 20)]);
+        assert_eq!(sparse.name.as_deref(), Some(&b"real/name"[..]));
+
+        let attrs = PaxAttributes::parse(&make_pax_data(&[("path", "foo")])).unwrap();
+        assert!(Sparse::from_pax(&attrs, Vec::new()).unwrap().is_none());
+    }
+
+    #[test]
+    fn pax_map_in_data() {
+        let mut sparse = Sparse::default();
+        let mut data = vec![0u8; BLOCK_SIZE];
+        data[..8].copy_from_slice(b"2\n0\n10\n5");
+        assert!(!sparse.extend_from_pax_map(&data).unwrap());
+
+        let mut map = "2\n0\n10\n".to_string();
+        map.push_str(&"1".repeat(BLOCK_SIZE - map.len() - 1));
+        map.push('\n');
+        map.push_str("20\n");
+        let mut data = map.into_bytes();
+        data.resize(2 * BLOCK_SIZE, 0);
+        sparse.extend_from_pax_map(&data).unwrap_err();
+
+        let mut data = b"2\n0\n10\n500\n20\n".to_vec();
+        data.resize(BLOCK_SIZE, 0);
+        assert!(sparse.extend_from_pax_map(&data).unwrap());
+        assert_eq!(sparse.regions, [region(0, 10), region(500, 20)]);
+        assert_eq!(sparse.map_len, BLOCK_SIZE as u64);
+
+        let regions = (0..100).map(|i| region(i * 1000, i)).collect::<Vec<_>>();
+        let mut sparse = Sparse {
+            regions: regions.clone(),
+            size: 100_000,
+            ..Sparse::default()
+        };
+        let data = sparse.encode_pax_map();
+        assert_eq!(data.len() as u64, sparse.map_len);
+        assert_eq!(data.len() % BLOCK_SIZE, 0);
+        let mut parsed = Sparse::default();
+        assert!(parsed.extend_from_pax_map(&data).unwrap());
+        assert_eq!(parsed.regions, regions);
+    }
+}
@@ -3,7 +3,7 @@ use std::io::{Error as IoError, ErrorKind, Result};
 
 use crate::TRACING_ENABLED;
 
-use super::block::{BLOCK_SIZE, Header};
+use super::block::BLOCK_SIZE;
 
 #[derive(Debug)]
 pub enum Error {
@@ -89,19 +89,32 @@ impl State {
     ///
     /// Will panic if the current state is not a marker state.
     #[inline]
-    pub fn take_marker(&mut self, header: Option<&Header>) -> Result<()> {
+    pub fn take_marker(&mut self, len: Option<u64>) -> Result<()> {
         assert!(self.is_marker(), "not a marker: {self:?}");
-        let (state, pos) = self.next(&[], header)?;
+        let (state, pos) = self.next(&[], len)?;
         debug_assert_eq!(pos, 0);
         *self = state;
         Ok(())
     }
 
+    /// Extends the data of the entry being received by `amt` bytes, for data
+    /// that turns out to precede the entry data but is not accounted for in
+    /// the entry size, like old GNU sparse extension blocks.
+    ///
+    /// Will panic if not receiving entry data.
+    #[inline]
+    pub fn extend_data(&mut self, amt: u64) {
+        match self {
+            Self::ReceivingData(remaining) => *remaining += amt,
+            s => panic!("not receiving data: {s:?}"),
+        }
+    }
+
     /// Takes each slice in order and transitions states as needed. Returns
     /// the final state and number of bytes read. Returns early if another
     /// header is received or EOF is reached.
     #[inline]
-    pub fn take_slices<'a, I>(self, slices: I, hdr: Option<&Header>) -> Result<(Self, usize)>
+    pub fn take_slices<'a, I>(self, slices: I, len: Option<u64>) -> Result<(Self, usize)>
     where
         I: Iterator<Item = &'a [u8]>,
     {
@@ -115,7 +128,7 @@ impl State {
                 continue;
             }
 
-            let next = state.take_until(&stop, buf, hdr)?;
+            let next = state.take_until(&stop, buf, len)?;
             state = next.0;
             cur += next.1;
             needs_next = false;
@@ -127,7 +140,7 @@ impl State {
 
         if needs_next {
             // Make sure to call next at least once to ensure forward progress.
-            let next = state.take_until(&stop, &[], hdr)?;
+            let next = state.take_until(&stop, &[], len)?;
             state = next.0;
             cur += next.1;
         }
@@ -138,19 +151,14 @@ impl State {
     /// Transitions states until one of the given stop states is reached or
     /// the buffer is exhausted, and returns the state and number of bytes read.
     #[inline]
-    pub fn take_until(
-        self,
-        stop: &[Self],
-        buf: &[u8],
-        header: Option<&Header>,
-    ) -> Result<(Self, usize)> {
+    pub fn take_until(self, stop: &[Self], buf: &[u8], len: Option<u64>) -> Result<(Self, usize)> {
         let mut state = self;
         let mut cur = 0usize;
         let mut buf = buf;
 
         // Call next at least once to ensure forward progress.
         loop {
-            let next = state.next(buf, header)?;
+            let next = state.next(buf, len)?;
 
             state = next.0;
             cur += next.1;
@@ -167,9 +175,14 @@ impl State {
     /// Transitions to the next state and returns the state and number of
     /// bytes read.
     ///
+    /// `len` is the number of data bytes of the current entry, as recorded
+    /// in its header or any extension headers preceding it. It is only
+    /// required for transitions out of [Self::ReceivedHeader] and
+    /// [Self::ReceivedData].
+    ///
     /// An empty buffer, despite being empty, will still lead to a state
     /// transition around a marker.
-    pub fn next(self, buf: &[u8], header: Option<&Header>) -> Result<(Self, usize)> {
+    pub fn next(self, buf: &[u8], len: Option<u64>) -> Result<(Self, usize)> {
         fn advance(buf: &[u8], max: usize) -> usize {
             max.min(buf.len())
         }
@@ -208,13 +221,13 @@ impl State {
             }
 
             Self::ReceivedHeader => {
-                assert!(header.is_some(), "header cannot be nil for state {self:?}");
-                let rem = header.unwrap().entry_size()?;
+                let rem = len.unwrap_or_else(|| panic!("len cannot be nil for state {self:?}"));
                 Self::ReceivingData(rem)
             }
 
             Self::ReceivingData(mut rem) => {
-                let len = advance(buf, rem as usize);
+                // Data may be larger than what's addressable in memory.
+                let len = advance(buf, usize::try_from(rem).unwrap_or(usize::MAX));
                 cur += len;
                 rem -= len as u64;
 
@@ -228,8 +241,7 @@ impl State {
             }
 
             Self::ReceivedData => {
-                assert!(header.is_some(), "header cannot be nil for state {self:?}");
-                let rem = header.unwrap().entry_size()?;
+                let rem = len.unwrap_or_else(|| panic!("len cannot be nil for state {self:?}"));
                 let align = rem.next_multiple_of(BLOCK_SIZE as u64) - rem;
                 Self::AligningData(align as usize)
             }
@@ -291,18 +303,18 @@ mod tests {
     #[test]
     fn basic() {
         let data = make_archive_data(&[("1000", 1000)]);
-        let mut hdr: Option<&Header> = None;
+        let mut len: Option<u64> = None;
 
         let state = State::default();
         assert_eq!(state, State::ExpectingHeader);
         let d = &data[..];
 
-        let (state, pos) = state.next(d, hdr).unwrap();
+        let (state, pos) = state.next(d, len).unwrap();
         assert_eq!(state, State::ReceivingHeader(BLOCK_SIZE, true));
         assert_eq!(pos, 0);
 
         let n = 250usize;
-        let (state, pos) = state.next(&d[..n], hdr).unwrap();
+        let (state, pos) = state.next(&d[..n], len).unwrap();
         assert_eq!(state, State::ReceivingHeader(BLOCK_SIZE - n, false));
         assert_eq!(pos, n);
         let d = &d[n..];
@@ -310,60 +322,66 @@ mod tests {
         {
             // test that the state transition can be identified midway through the buffer
             let n = 300usize;
-            let (state, pos) = state.next(&d[..n], hdr).unwrap();
+            let (state, pos) = state.next(&d[..n], len).unwrap();
             assert_eq!((state, pos), (State::ReceivedHeader, 262));
         }
 
         let n = 262usize;
-        let (state, pos) = state.next(&d[..n], hdr).unwrap();
+        let (state, pos) = state.next(&d[..n], len).unwrap();
         assert_eq!(state, State::ReceivedHeader);
         assert_eq!(pos, n);
-        hdr = Some(Block::from_bytes(&data[..BLOCK_SIZE]).as_header().unwrap());
+        len = Some(
+            Block::from_bytes(&data[..BLOCK_SIZE])
+                .as_header()
+                .unwrap()
+                .entry_size()
+                .unwrap(),
+        );
         let d = &d[n..];
 
-        let (state, pos) = state.next(d, hdr).unwrap();
+        let (state, pos) = state.next(d, len).unwrap();
         assert_eq!(state, State::ReceivingData(1000));
         assert_eq!(pos, 0);
 
         let n = 500usize;
-        let (state, pos) = state.next(&d[..n], hdr).unwrap();
+        let (state, pos) = state.next(&d[..n], len).unwrap();
         assert_eq!(state, State::ReceivingData((1000 - n) as u64));
         assert_eq!(pos, n);
         let d = &d[n..];
 
         let n = 500usize;
-        let (state, pos) = state.next(&d[..n], hdr).unwrap();
+        let (state, pos) = state.next(&d[..n], len).unwrap();
         assert_eq!(state, State::ReceivedData);
         assert_eq!(pos, n);
         let d = &d[n..];
 
-        let (state, pos) = state.next(d, hdr).unwrap();
+        let (state, pos) = state.next(d, len).unwrap();
         assert_eq!(state, State::AligningData(24));
         assert_eq!(pos, 0);
 
         let n = 10usize;
-        let (state, pos) = state.next(&d[..n], hdr).unwrap();
+        let (state, pos) = state.next(&d[..n], len).unwrap();
         assert_eq!(state, State::AligningData(14));
         assert_eq!(pos, n);
         let d = &d[n..];
 
         let n = 14usize;
-        let (state, pos) = state.next(&d[..n], hdr).unwrap();
+        let (state, pos) = state.next(&d[..n], len).unwrap();
         assert_eq!(state, State::AlignedData);
         assert_eq!(pos, n);
-        hdr = None;
+        len = None;
         let d = &d[n..];
 
-        let (state, pos) = state.next(d, hdr).unwrap();
+        let (state, pos) = state.next(d, len).unwrap();
         assert_eq!(state, State::ExpectingHeader);
         assert_eq!(pos, 0);
 
-        let (state, pos) = state.next(d, hdr).unwrap();
+        let (state, pos) = state.next(d, len).unwrap();
         assert_eq!(state, State::ReceivingHeader(BLOCK_SIZE, true));
         assert_eq!(pos, 0);
 
         let n = 256usize;
-        let (state, pos) = state.next(&d[..n], hdr).unwrap();
+        let (state, pos) = state.next(&d[..n], len).unwrap();
         assert_eq!(state, State::ReceivingHeader(BLOCK_SIZE - n, true));
         assert_eq!(pos, n);
         let d = &d[n..];
@@ -371,25 +389,25 @@ mod tests {
         {
             // test that the state transition can be identified midway through the buffer
             let n = 356usize;
-            let (state, pos) = state.next(&d[..n], hdr).unwrap();
+            let (state, pos) = state.next(&d[..n], len).unwrap();
             assert_eq!(state, State::ReceivingEof(BLOCK_SIZE));
             assert_eq!(pos, n - 100);
         }
 
         let n = 256usize;
-        let (state, pos) = state.next(&d[..n], hdr).unwrap();
+        let (state, pos) = state.next(&d[..n], len).unwrap();
         assert_eq!(state, State::ReceivingEof(BLOCK_SIZE));
         assert_eq!(pos, n);
         let d = &d[n..];
 
         let n = 256usize;
-        let (state, pos) = state.next(&d[..n], hdr).unwrap();
+        let (state, pos) = state.next(&d[..n], len).unwrap();
         assert_eq!(state, State::ReceivingEof(BLOCK_SIZE - n));
         assert_eq!(pos, n);
         let d = &d[n..];
 
         let n = 256usize;
-        let (state, pos) = state.next(&d[..n], hdr).unwrap();
+        let (state, pos) = state.next(&d[..n], len).unwrap();
         assert_eq!(state, State::ReceivedEof);
         assert_eq!(pos, n);
         let d = &d[n..];
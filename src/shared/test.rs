@@ -1,3 +1,9 @@
+use std::io::{Cursor, Result, SeekFrom};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncSeek, ReadBuf};
+
 use super::block::{BLOCK_SIZE, Header};
 
 pub fn make_archive_data(entries: &[(&str, usize)]) -> Vec<u8> {
@@ -40,3 +46,84 @@ pub fn make_entry_data(size: usize) -> Vec<u8> {
 pub fn make_eof_data() -> Vec<u8> {
     vec![0u8; 1024]
 }
+
+pub fn make_pax_data(records: &[(&str, &str)]) -> Vec<u8> {
+    records
+        .iter()
+        .flat_map(|(key, value)| {
+            let rec = format!(" {key}={value}\n");
+            let mut len = rec.len();
+            // The length prefix counts its own digits.
+            while format!("{len}{rec}").len() != len {
+                len = format!("{len}{rec}").len();
+            }
+            format!("{len}{rec}").into_bytes()
+        })
+        .collect()
+}
+
+/// Makes a header like the ones star writes by default: ustar with a shorter
+/// prefix, followed by atime, ctime and a `tar\0` trailer.
+pub fn make_star_header(path: &str, atime: u64, ctime: u64) -> Header {
+    let mut header = Header::new_ustar();
+    let (prefix, name) = path.rsplit_once('/').unwrap_or(("", path));
+    header.set_path(name).unwrap();
+    header.set_size(0);
+    let bytes = header.as_mut_bytes();
+    bytes[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    bytes[476..488].copy_from_slice(format!("{atime:011o}\0").as_bytes());
+    bytes[488..500].copy_from_slice(format!("{ctime:011o}\0").as_bytes());
+    bytes[508..].copy_from_slice(b"tar\0");
+    header.set_cksum();
+    header
+}
+
+/// A reader that returns at most `chunk` bytes per read, as sockets and
+/// decompressors may.
+pub struct ChunkedReader<'a> {
+    pub data: &'a [u8],
+    pub chunk: usize,
+}
+
+impl AsyncRead for ChunkedReader<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let len = self.data.len().min(self.chunk).min(buf.remaining());
+        let (chunk, rest) = self.data.split_at(len);
+        buf.put_slice(chunk);
+        self.data = rest;
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A seekable reader that counts the bytes read from it.
+pub struct CountingReader<'a> {
+    pub io: Cursor<&'a [u8]>,
+    pub read: usize,
+}
+
+impl AsyncRead for CountingReader<'_> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        let len = buf.filled().len();
+        let res = Pin::new(&mut self.io).poll_read(cx, buf);
+        self.read += buf.filled().len() - len;
+        res
+    }
+}
+
+impl AsyncSeek for CountingReader<'_> {
+    fn start_seek(mut self: Pin<&mut Self>, pos: SeekFrom) -> Result<()> {
+        Pin::new(&mut self.io).start_seek(pos)
+    }
+
+    fn poll_complete(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        Pin::new(&mut self.io).poll_complete(cx)
+    }
+}
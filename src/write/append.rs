@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::fs::Metadata;
+use std::io::{Error as IoError, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncWrite};
+
+use crate::{Archive, EntryType, HeaderBuilder};
+
+#[derive(Debug)]
+pub(crate) struct AppendOptions {
+    pub follow_symlinks: bool,
+}
+
+impl Default for AppendOptions {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            follow_symlinks: true,
+        }
+    }
+}
+
+/// A file to append, along with its path in the archive and the number of
+/// directories above it.
+struct Pending {
+    src: PathBuf,
+    path: PathBuf,
+    depth: usize,
+}
+
+pub(crate) async fn append_dir_all<W: AsyncWrite + Unpin>(
+    archive: &mut Archive<W>,
+    path: &Path,
+    src: &Path,
+) -> Result<()> {
+    let follow = archive.append.follow_symlinks;
+    let mut owners = Owners::default();
+    let mut links: HashMap<_, PathBuf> = HashMap::new();
+    let mut ancestors = Vec::new();
+    let mut stack = vec![Pending {
+        src: src.to_path_buf(),
+        path: path.to_path_buf(),
+        depth: 0,
+    }];
+
+    // Files are appended depth first, each directory followed by its
+    // contents in order of name, so the same tree always makes the same
+    // archive.
+    while let Some(Pending { src, path, depth }) = stack.pop() {
+        let meta = if follow {
+            fs::metadata(&src).await?
+        } else {
+            fs::symlink_metadata(&src).await?
+        };
+        let file_type = meta.file_type();
+        let mut builder = match HeaderBuilder::from_metadata(&path, &meta) {
+            Ok(builder) => owners.add_names(builder, &meta).await?,
+            // Sockets are left out.
+            Err(err) if err.kind() == ErrorKind::Unsupported => continue,
+            Err(err) => return Err(err),
+        };
+
+        if file_type.is_dir() {
+            // Following symlinks may lead back to a directory we are in.
+            ancestors.truncate(depth);
+            let id = file_id(&meta);
+            if id.is_some() && ancestors.contains(&id) {
+                return Err(IoError::new(
+                    ErrorKind::InvalidInput,
+                    format!("filesystem loop at {}", src.display()),
+                ));
+            }
+            ancestors.push(id);
+
+            if path != Path::new("") {
+                archive.add_entry_with_builder(&builder).await?;
+            }
+
+            let mut names = Vec::new();
+            let mut dir = fs::read_dir(&src).await?;
+            while let Some(child) = dir.next_entry().await? {
+                names.push(child.file_name());
+            }
+            names.sort();
+            stack.extend(names.into_iter().rev().map(|name| Pending {
+                src: src.join(&name),
+                path: path.join(&name),
+                depth: depth + 1,
+            }));
+        } else if file_type.is_symlink() {
+            builder = builder.link_name(fs::read_link(&src).await?);
+            archive.add_entry_with_builder(&builder).await?;
+        } else if file_type.is_file() {
+            // Files with more than one link are archived once, and as hard
+            // links to the first path they were found at after that.
+            if let Some(id) = file_id(&meta).filter(|_| link_count(&meta) > 1) {
+                if let Some(target) = links.get(&id) {
+                    builder = builder.kind(EntryType::Link).link_name(target).size(0);
+                    archive.add_entry_with_builder(&builder).await?;
+                    continue;
+                }
+                links.insert(id, path.clone());
+            }
+
+            let size = meta.len();
+            let file = fs::File::open(&src).await?;
+            let mut entry = archive.add_entry_with_builder(&builder).await?;
+            let n = tokio::io::copy(&mut file.take(size), &mut entry).await?;
+            if n < size {
+                return Err(IoError::new(
+                    ErrorKind::UnexpectedEof,
+                    format!("file shrank while being archived: {}", src.display()),
+                ));
+            }
+        } else {
+            archive.add_entry_with_builder(&builder).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(unix)]
+fn file_id(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+fn link_count(meta: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    meta.nlink()
+}
+
+#[cfg(not(unix))]
+fn link_count(_meta: &Metadata) -> u64 {
+    1
+}
+
+/// A cache of user and group names by id, as looking them up may be slow.
+#[derive(Default)]
+#[cfg_attr(not(unix), allow(dead_code))]
+struct Owners {
+    users: HashMap<u32, Option<String>>,
+    groups: HashMap<u32, Option<String>>,
+}
+
+impl Owners {
+    /// Sets the owner names of a file on its builder, where they can be
+    /// looked up.
+    #[cfg(unix)]
+    async fn add_names(
+        &mut self,
+        builder: HeaderBuilder,
+        meta: &Metadata,
+    ) -> Result<HeaderBuilder> {
+        use std::os::unix::fs::MetadataExt;
+        let mut builder = builder;
+        if let Some(name) = self.username(meta.uid()).await? {
+            builder = builder.username(name);
+        }
+        if let Some(name) = self.groupname(meta.gid()).await? {
+            builder = builder.groupname(name);
+        }
+        Ok(builder)
+    }
+
+    #[cfg(not(unix))]
+    async fn add_names(
+        &mut self,
+        builder: HeaderBuilder,
+        _meta: &Metadata,
+    ) -> Result<HeaderBuilder> {
+        Ok(builder)
+    }
+
+    #[cfg(unix)]
+    async fn username(&mut self, uid: u32) -> Result<Option<String>> {
+        if let Some(name) = self.users.get(&uid) {
+            return Ok(name.clone());
+        }
+        let name = lookup(move || passwd::user_name(uid)).await?;
+        self.users.insert(uid, name.clone());
+        Ok(name)
+    }
+
+    #[cfg(unix)]
+    async fn groupname(&mut self, gid: u32) -> Result<Option<String>> {
+        if let Some(name) = self.groups.get(&gid) {
+            return Ok(name.clone());
+        }
+        let name = lookup(move || passwd::group_name(gid)).await?;
+        self.groups.insert(gid, name.clone());
+        Ok(name)
+    }
+}
+
+#[cfg(unix)]
+async fn lookup<F>(f: F) -> Result<Option<String>>
+where
+    F: FnOnce() -> Result<Option<String>> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(IoError::other)?
+}
+
+#[cfg(unix)]
+mod passwd {
+    use std::ffi::CStr;
+    use std::io::{Error as IoError, Result};
+    use std::mem::MaybeUninit;
+    use std::ptr;
+
+    pub fn user_name(uid: u32) -> Result<Option<String>> {
+        with_buffer(|buf| {
+            let mut pwd = MaybeUninit::<libc::passwd>::uninit();
+            let mut result = ptr::null_mut();
+            // SAFETY: all pointers are valid for the duration of the call,
+            // and `pw_name` points into `buf` when `result` is set.
+            unsafe {
+                let rc = libc::getpwuid_r(
+                    uid,
+                    pwd.as_mut_ptr(),
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                );
+                (rc, (!result.is_null()).then(|| name((*result).pw_name)))
+            }
+        })
+    }
+
+    pub fn group_name(gid: u32) -> Result<Option<String>> {
+        with_buffer(|buf| {
+            let mut grp = MaybeUninit::<libc::group>::uninit();
+            let mut result = ptr::null_mut();
+            // SAFETY: as above, with `gr_name`.
+            unsafe {
+                let rc = libc::getgrgid_r(
+                    gid,
+                    grp.as_mut_ptr(),
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                );
+                (rc, (!result.is_null()).then(|| name((*result).gr_name)))
+            }
+        })
+    }
+
+    /// Calls a reentrant lookup function with a buffer for its strings,
+    /// growing the buffer for as long as it's too small.
+    fn with_buffer<F>(mut f: F) -> Result<Option<String>>
+    where
+        F: FnMut(&mut [libc::c_char]) -> (libc::c_int, Option<String>),
+    {
+        let mut buf = vec![0; 1024];
+        loop {
+            match f(&mut buf) {
+                (0, name) => return Ok(name),
+                (libc::ERANGE, _) if buf.len() < 1 << 20 => buf.resize(buf.len() * 2, 0),
+                (rc, _) => return Err(IoError::from_raw_os_error(rc)),
+            }
+        }
+    }
+
+    /// SAFETY: `ptr` must point to a valid C string.
+    unsafe fn name(ptr: *const libc::c_char) -> String {
+        unsafe { CStr::from_ptr(ptr) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
@@ -0,0 +1,252 @@
+use std::fs::Metadata;
+use std::io::{Error as IoError, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::shared::block::{EntryType, Header};
+use crate::shared::ext::EntryExtensions;
+
+use super::ext::{EntryMetadata, Format, encode};
+
+/// A builder for entry headers, that always produces finalized headers.
+///
+/// Fields that don't fit in a ustar header, such as long paths, are written
+/// in extension entries ahead of the header by
+/// [Archive::add_entry_with_builder][crate::Archive::add_entry_with_builder],
+/// according to the [format][Self::format].
+///
+/// ```
+/// # use std::io::Result;
+/// # #[tokio::main(flavor = "current_thread")] async fn main() -> Result<()> {
+/// use tokio::io::AsyncWriteExt;
+/// use tario::{Archive, HeaderBuilder};
+///
+/// let mut archive = Archive::new(Vec::new());
+/// let builder = HeaderBuilder::new("hello.txt").size(12).mode(0o600);
+/// let mut entry = archive.add_entry_with_builder(&builder).await?;
+/// entry.write_all(b"hello world!").await?;
+/// archive.finish().await?;
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeaderBuilder {
+    path: PathBuf,
+    size: u64,
+    meta: EntryMetadata,
+    format: Option<Format>,
+}
+
+impl HeaderBuilder {
+    /// Creates a builder for a regular file with the given path, no data
+    /// and the [default metadata][EntryMetadata::default].
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            size: 0,
+            meta: EntryMetadata::default(),
+            format: None,
+        }
+    }
+
+    /// Creates a builder for an entry with the given path, and the kind,
+    /// size, mode, owner ids and mtime of the given filesystem metadata.
+    ///
+    /// The link name of symlinks and the owner names are not part of the
+    /// metadata, so they must be set separately. Fails for sockets, which
+    /// cannot be archived.
+    pub fn from_metadata<P: AsRef<Path>>(path: P, meta: &Metadata) -> Result<Self> {
+        let file_type = meta.file_type();
+        let mut builder = Self::new(path).mtime(meta.modified()?);
+
+        if file_type.is_file() {
+            builder = builder.size(meta.len());
+        } else if file_type.is_dir() {
+            builder = builder.kind(EntryType::Directory);
+        } else if file_type.is_symlink() {
+            builder = builder.kind(EntryType::Symlink);
+        } else {
+            builder = special_file(builder, meta)?;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            builder = builder
+                .mode(meta.mode() & 0o7777)
+                .uid(meta.uid().into())
+                .gid(meta.gid().into());
+        }
+
+        #[cfg(not(unix))]
+        {
+            let mode = if file_type.is_dir() { 0o755 } else { 0o644 };
+            let readonly = meta.permissions().readonly();
+            builder = builder.mode(if readonly { mode & !0o222 } else { mode });
+        }
+
+        Ok(builder)
+    }
+
+    /// Sets the size of the entry data.
+    pub fn size(mut self, size: u64) -> Self {
+        self.size = size;
+        self
+    }
+
+    /// Sets the kind of the entry.
+    pub fn kind(mut self, kind: EntryType) -> Self {
+        self.meta.kind = kind;
+        self
+    }
+
+    /// Sets the permission bits of the entry.
+    pub fn mode(mut self, mode: u32) -> Self {
+        self.meta.mode = mode;
+        self
+    }
+
+    /// Sets the owner user id of the entry.
+    pub fn uid(mut self, uid: u64) -> Self {
+        self.meta.uid = uid;
+        self
+    }
+
+    /// Sets the owner group id of the entry.
+    pub fn gid(mut self, gid: u64) -> Self {
+        self.meta.gid = gid;
+        self
+    }
+
+    /// Sets the owner user name of the entry.
+    pub fn username<S: Into<String>>(mut self, name: S) -> Self {
+        self.meta.username = Some(name.into());
+        self
+    }
+
+    /// Sets the owner group name of the entry.
+    pub fn groupname<S: Into<String>>(mut self, name: S) -> Self {
+        self.meta.groupname = Some(name.into());
+        self
+    }
+
+    /// Sets the modification time of the entry.
+    pub fn mtime(mut self, mtime: SystemTime) -> Self {
+        self.meta.mtime = mtime;
+        self
+    }
+
+    /// Sets the access time of the entry. This is recorded in a PAX
+    /// extended header, or in the GNU header with [Format::Gnu].
+    pub fn atime(mut self, atime: SystemTime) -> Self {
+        self.meta.atime = Some(atime);
+        self
+    }
+
+    /// Sets the status change time of the entry. This is recorded in a PAX
+    /// extended header, or in the GNU header with [Format::Gnu].
+    pub fn ctime(mut self, ctime: SystemTime) -> Self {
+        self.meta.ctime = Some(ctime);
+        self
+    }
+
+    /// Sets the target of a symlink or hard link entry.
+    pub fn link_name<P: AsRef<Path>>(mut self, link_name: P) -> Self {
+        self.meta.link_name = Some(link_name.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets the major and minor numbers of a device entry.
+    pub fn device(mut self, major: u32, minor: u32) -> Self {
+        self.meta.device = Some((major, minor));
+        self
+    }
+
+    /// Sets the format extension for fields that don't fit in a ustar
+    /// header. Defaults to the [format of the archive][crate::Archive::set_format]
+    /// the entry is added to.
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// Returns the metadata of the entry.
+    pub fn metadata(&self) -> &EntryMetadata {
+        &self.meta
+    }
+
+    /// Builds the finalized header of the entry, assuming [Format::Pax]
+    /// unless a format is set. The mtime is rounded down to the second.
+    ///
+    /// This fails with [ErrorKind::InvalidInput] if the entry needs
+    /// extension entries ahead of the header to be represented, in which
+    /// case it must be added to an archive with
+    /// [Archive::add_entry_with_builder][crate::Archive::add_entry_with_builder].
+    pub fn build(&self) -> Result<Header> {
+        let mut builder = self.clone();
+        if let Ok(since) = self.meta.mtime.duration_since(UNIX_EPOCH) {
+            builder.meta.mtime = UNIX_EPOCH + Duration::from_secs(since.as_secs());
+        }
+        let (header, ext) = builder.encode(Format::default())?;
+        if ext.pax.is_empty() && ext.long_name.is_none() && ext.long_link.is_none() {
+            Ok(header)
+        } else {
+            Err(IoError::new(
+                ErrorKind::InvalidInput,
+                "entry does not fit in a header without extensions",
+            ))
+        }
+    }
+
+    /// Builds the finalized header of the entry along with its extensions,
+    /// using the given format unless one is set.
+    pub(crate) fn encode(&self, format: Format) -> Result<(Header, EntryExtensions)> {
+        let format = self.format.unwrap_or(format);
+        encode(&self.path, self.size, &self.meta, format)
+    }
+}
+
+#[cfg(unix)]
+fn special_file(builder: HeaderBuilder, meta: &Metadata) -> Result<HeaderBuilder> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let file_type = meta.file_type();
+    if file_type.is_fifo() {
+        return Ok(builder.kind(EntryType::Fifo));
+    }
+
+    let kind = if file_type.is_char_device() {
+        EntryType::Char
+    } else if file_type.is_block_device() {
+        EntryType::Block
+    } else {
+        return Err(unsupported("sockets cannot be archived"));
+    };
+    let (major, minor) = device_numbers(meta)?;
+    Ok(builder.kind(kind).device(major, minor))
+}
+
+#[cfg(not(unix))]
+fn special_file(_builder: HeaderBuilder, _meta: &Metadata) -> Result<HeaderBuilder> {
+    Err(unsupported("unsupported file type"))
+}
+
+/// Splits the device number of a device file the way glibc does.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn device_numbers(meta: &Metadata) -> Result<(u32, u32)> {
+    use std::os::unix::fs::MetadataExt;
+    let rdev = meta.rdev();
+    let major = ((rdev >> 32) & 0xffff_f000) | ((rdev >> 8) & 0x0000_0fff);
+    let minor = ((rdev >> 12) & 0xffff_ff00) | (rdev & 0x0000_00ff);
+    Ok((major as u32, minor as u32))
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn device_numbers(_meta: &Metadata) -> Result<(u32, u32)> {
+    Err(unsupported(
+        "device numbers are not supported on this platform",
+    ))
+}
+
+fn unsupported(msg: &'static str) -> IoError {
+    IoError::new(ErrorKind::Unsupported, msg)
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+use std::io::{ErrorKind, Result};
+use std::mem;
+use std::pin::Pin;
+use std::task::{Context, Poll, ready};
+
+#[cfg(feature = "bzip2")]
+use async_compression::tokio::write::BzEncoder;
+#[cfg(feature = "gzip")]
+use async_compression::tokio::write::GzipEncoder;
+#[cfg(feature = "xz")]
+use async_compression::tokio::write::XzEncoder;
+#[cfg(feature = "zstd")]
+use async_compression::tokio::write::ZstdEncoder;
+use tokio::io::AsyncWrite;
+
+use crate::shared::compression::Compression;
+
+/// A writer that compresses what is written to it into the writer it wraps.
+///
+/// This is what [Archive::new_compressed][crate::Archive::new_compressed]
+/// writes to. The compressed stream is made of one frame, or member in gzip
+/// terms, unless frames are ended with [Self::set_frame_per_flush] or
+/// [Archive::set_frame_per_entry][crate::Archive::set_frame_per_entry], in
+/// which case each frame can be decompressed on its own.
+#[derive(Debug)]
+pub struct Encoder<W> {
+    io: W,
+    compression: Compression,
+    frame: Option<Frame>,
+    frame_per_flush: bool,
+
+    /// Compressed bytes waiting to be written, from `pos` on.
+    out: Vec<u8>,
+    pos: usize,
+
+    /// The number of compressed bytes taken from frames so far.
+    produced: u64,
+}
+
+/// An encoder for one frame, compressing into a buffer that is drained into
+/// the writer.
+trait FrameEncoder: AsyncWrite + Unpin + Send + Sync + fmt::Debug {
+    fn buf_mut(&mut self) -> &mut Vec<u8>;
+}
+
+macro_rules! frame_encoder {
+    ($feature:literal, $encoder:ident) => {
+        #[cfg(feature = $feature)]
+        impl FrameEncoder for $encoder<Vec<u8>> {
+            fn buf_mut(&mut self) -> &mut Vec<u8> {
+                self.get_mut()
+            }
+        }
+    };
+}
+
+frame_encoder!("gzip", GzipEncoder);
+frame_encoder!("zstd", ZstdEncoder);
+frame_encoder!("xz", XzEncoder);
+frame_encoder!("bzip2", BzEncoder);
+
+type Frame = Box<dyn FrameEncoder>;
+
+/// Starts a frame, unless nothing is compressed.
+fn new_frame(compression: Compression) -> Option<Frame> {
+    match compression {
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Some(Box::new(GzipEncoder::new(Vec::new()))),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Some(Box::new(ZstdEncoder::new(Vec::new()))),
+        #[cfg(feature = "xz")]
+        Compression::Xz => Some(Box::new(XzEncoder::new(Vec::new()))),
+        #[cfg(feature = "bzip2")]
+        Compression::Bzip2 => Some(Box::new(BzEncoder::new(Vec::new()))),
+        // Anything else was rejected as not enabled by [Encoder::new].
+        _ => None,
+    }
+}
+
+impl<W> Encoder<W> {
+    /// Wraps the given writer, to compress what is written with the given
+    /// compression. [Compression::None] writes through as is.
+    ///
+    /// This fails with [ErrorKind::Unsupported] if the feature for the
+    /// compression is not enabled.
+    pub fn new(io: W, compression: Compression) -> Result<Self> {
+        Ok(Self {
+            io,
+            compression: compression.check_enabled()?,
+            frame: None,
+            frame_per_flush: false,
+            out: Vec::new(),
+            pos: 0,
+            produced: 0,
+        })
+    }
+
+    /// Returns the compression of the stream.
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Sets whether flushing ends the current frame, rather than only
+    /// compressing what is buffered. Defaults to false.
+    ///
+    /// A new frame is started with the next write, so flushing repeatedly
+    /// doesn't make empty frames.
+    pub fn set_frame_per_flush(&mut self, enabled: bool) {
+        self.frame_per_flush = enabled;
+    }
+
+    /// Returns the number of compressed bytes produced so far. This is
+    /// exact after a frame ends, when it's the offset the next frame starts
+    /// at, but may lag behind while compressing a frame.
+    pub fn position(&self) -> u64 {
+        self.produced
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.io
+    }
+
+    /// Consumes this encoder and returns the underlying writer. Anything
+    /// not yet flushed is lost.
+    pub fn into_inner(self) -> W {
+        self.io
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Encoder<W> {
+    /// Ends the current frame, if any, and writes all of it to the
+    /// underlying writer.
+    pub(crate) fn poll_end_frame(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        ready!(self.poll_drain(cx))?;
+
+        if let Some(frame) = &mut self.frame {
+            ready!(Pin::new(frame).poll_shutdown(cx))?;
+            self.take();
+            self.frame = None;
+        }
+
+        self.poll_drain(cx)
+    }
+
+    /// Writes the compressed bytes produced so far to the underlying writer.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        loop {
+            while self.pos < self.out.len() {
+                let n = ready!(Pin::new(&mut self.io).poll_write(cx, &self.out[self.pos..]))?;
+                if n == 0 {
+                    return Poll::Ready(Err(ErrorKind::WriteZero.into()));
+                }
+                self.pos += n;
+            }
+            self.out.clear();
+            self.pos = 0;
+
+            if !self.take() {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+
+    /// Moves what the current frame has compressed so far into our output
+    /// buffer, which must be empty. Returns whether there was anything.
+    fn take(&mut self) -> bool {
+        debug_assert!(self.out.is_empty());
+        let Some(frame) = &mut self.frame else {
+            return false;
+        };
+        mem::swap(&mut self.out, frame.buf_mut());
+        self.produced += self.out.len() as u64;
+        !self.out.is_empty()
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for Encoder<W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_drain(cx))?;
+
+        if this.frame.is_none() {
+            this.frame = new_frame(this.compression);
+        }
+        match &mut this.frame {
+            Some(frame) => Pin::new(frame).poll_write(cx, buf),
+            None => {
+                let n = ready!(Pin::new(&mut this.io).poll_write(cx, buf))?;
+                this.produced += n as u64;
+                Poll::Ready(Ok(n))
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        if this.frame_per_flush {
+            ready!(this.poll_end_frame(cx))?;
+        } else if let Some(frame) = &mut this.frame {
+            ready!(Pin::new(frame).poll_flush(cx))?;
+            ready!(this.poll_drain(cx))?;
+        }
+        Pin::new(&mut this.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_end_frame(cx))?;
+        Pin::new(&mut this.io).poll_shutdown(cx)
+    }
+}
@@ -1,12 +1,21 @@
 use std::fmt;
 use std::io::{Error as IoError, ErrorKind, Result};
+use std::sync::Arc;
 use std::task::Poll;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum WriteError {
     UnexpectedEof { expected: u64, received: u64 },
     WriteZero,
     OverlappingEntry,
+    UnfinishedEntry,
+    EntryOverflow,
+    IncompleteHeader,
+    UnfinalizedHeader,
+    InvalidMtime,
+    Padding(Arc<IoError>),
+    Finished,
+    Poisoned(Box<WriteError>),
 }
 
 impl WriteError {
@@ -16,11 +25,27 @@ impl WriteError {
             Self::UnexpectedEof { .. } => ErrorKind::UnexpectedEof,
             Self::WriteZero => ErrorKind::WriteZero,
             Self::OverlappingEntry => ErrorKind::Unsupported,
+            Self::UnfinishedEntry => ErrorKind::Unsupported,
+            Self::EntryOverflow => ErrorKind::InvalidInput,
+            Self::IncompleteHeader => ErrorKind::Unsupported,
+            Self::UnfinalizedHeader => ErrorKind::InvalidInput,
+            Self::InvalidMtime => ErrorKind::InvalidInput,
+            Self::Padding(cause) => cause.kind(),
+            Self::Finished => ErrorKind::Unsupported,
+            Self::Poisoned(cause) => cause.kind(),
         }
     }
 }
 
-impl std::error::Error for WriteError {}
+impl std::error::Error for WriteError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Padding(cause) => Some(&**cause),
+            Self::Poisoned(cause) => Some(cause),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for WriteError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -33,6 +58,18 @@ impl fmt::Display for WriteError {
             Self::OverlappingEntry => {
                 "cannot write new entry while another is being written".fmt(f)
             }
+            Self::UnfinishedEntry => "cannot finish archive while an entry is being written".fmt(f),
+            Self::EntryOverflow => "cannot write past the end of the entry".fmt(f),
+            Self::IncompleteHeader => "cannot continue after a header was partially written".fmt(f),
+            Self::UnfinalizedHeader => "cannot write a header without its checksum set".fmt(f),
+            Self::InvalidMtime => "cannot normalize an entry with an invalid mtime".fmt(f),
+            Self::Padding(cause) => {
+                format!("failed to write the padding of an entry: {cause}").fmt(f)
+            }
+            Self::Finished => "cannot write to a finished archive".fmt(f),
+            Self::Poisoned(cause) => {
+                format!("archive is unusable after an earlier error: {cause}").fmt(f)
+            }
         }
     }
 }
@@ -0,0 +1,343 @@
+use std::io::{Error as IoError, ErrorKind, Result};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::shared::block::{EntryType, Header, set_header_mtime};
+use crate::shared::ext::EntryExtensions;
+use crate::shared::pax::format_time;
+use crate::shared::sparse::{Sparse, SparseRegion};
+
+/// The largest value that fits in an 8-byte octal field, such as uid and gid.
+const MAX_OCTAL_8: u64 = 0o7777777;
+
+/// The largest value that fits in a 12-byte octal field, such as size and mtime.
+const MAX_OCTAL_12: u64 = 0o77777777777;
+
+/// The size of the uname and gname fields.
+const MAX_NAME_LEN: usize = 32;
+
+/// The path GNU tar uses for long name and long link entries.
+const GNU_LONG_LINK_PATH: &str = "././@LongLink";
+
+/// The format extension used to write entry metadata that doesn't fit in a
+/// ustar header, such as long paths.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// Write a PAX extended header ahead of the entry. This is understood by
+    /// virtually every modern tar implementation and can represent anything.
+    #[default]
+    Pax,
+
+    /// Write GNU long name and long link entries ahead of the entry, and
+    /// encode numeric fields that overflow in base-256. This is for older
+    /// tools that don't understand PAX; mtime precision beyond seconds and
+    /// user or group names longer than 31 bytes are lost.
+    Gnu,
+}
+
+impl Format {
+    /// Records an entry attribute that doesn't fit in its header.
+    fn extend(self, ext: &mut EntryExtensions, key: &[u8], value: &[u8]) {
+        match (self, key) {
+            (Self::Pax, _) => ext.pax.insert(key, value),
+            (Self::Gnu, b"path") => ext.long_name = Some(value.into()),
+            (Self::Gnu, b"linkpath") => ext.long_link = Some(value.into()),
+            // Other fields can only be represented as far as the header
+            // allows, which it's already been set to.
+            (Self::Gnu, _) => {}
+        }
+    }
+}
+
+/// Metadata of an entry to add to an archive with
+/// [Archive::add_entry_with_metadata][crate::Archive::add_entry_with_metadata].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryMetadata {
+    pub kind: EntryType,
+    pub mode: u32,
+    pub uid: u64,
+    pub gid: u64,
+    pub username: Option<String>,
+    pub groupname: Option<String>,
+    pub mtime: SystemTime,
+    /// The access time, which only PAX and GNU entries can record.
+    pub atime: Option<SystemTime>,
+    /// The status change time, which only PAX and GNU entries can record.
+    pub ctime: Option<SystemTime>,
+    pub link_name: Option<PathBuf>,
+    /// The major and minor numbers of character and block devices.
+    pub device: Option<(u32, u32)>,
+}
+
+impl Default for EntryMetadata {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            kind: EntryType::Regular,
+            mode: 0o644,
+            uid: 0,
+            gid: 0,
+            username: None,
+            groupname: None,
+            mtime: UNIX_EPOCH,
+            atime: None,
+            ctime: None,
+            link_name: None,
+            device: None,
+        }
+    }
+}
+
+/// Builds a finalized header for an entry along with the extensions for
+/// the fields the header cannot represent in the given format.
+pub fn encode(
+    path: &Path,
+    size: u64,
+    meta: &EntryMetadata,
+    format: Format,
+) -> Result<(Header, EntryExtensions)> {
+    let mut header = match format {
+        Format::Pax => Header::new_ustar(),
+        Format::Gnu => Header::new_gnu(),
+    };
+    let mut ext = EntryExtensions::default();
+
+    header.set_entry_type(meta.kind);
+    header.set_mode(meta.mode);
+
+    let name = normalize(path)?;
+    if header.set_path(path).is_err() {
+        // The path is valid but too long; store as much as fits in the
+        // header for readers that don't understand extensions.
+        format.extend(&mut ext, b"path", &name);
+        set_truncated(&mut header.as_old_mut().name, &name);
+        if let Some(ustar) = header.as_ustar_mut() {
+            ustar.prefix.fill(0);
+        }
+    }
+
+    if let Some(link_name) = &meta.link_name
+        && header.set_link_name(link_name).is_err()
+    {
+        let link_name = path_bytes(link_name)?;
+        format.extend(&mut ext, b"linkpath", &link_name);
+        set_truncated(&mut header.as_old_mut().linkname, &link_name);
+    }
+
+    // Numeric fields that overflow are also set in the header in base-256
+    // for the benefit of readers that understand that but not PAX.
+    header.set_size(size);
+    if size > MAX_OCTAL_12 {
+        format.extend(&mut ext, b"size", size.to_string().as_bytes());
+    }
+
+    header.set_uid(meta.uid);
+    if meta.uid > MAX_OCTAL_8 {
+        format.extend(&mut ext, b"uid", meta.uid.to_string().as_bytes());
+    }
+
+    header.set_gid(meta.gid);
+    if meta.gid > MAX_OCTAL_8 {
+        format.extend(&mut ext, b"gid", meta.gid.to_string().as_bytes());
+    }
+
+    if let Some(name) = &meta.username {
+        header.set_username(truncate_name(name))?;
+        if name.len() >= MAX_NAME_LEN {
+            format.extend(&mut ext, b"uname", name.as_bytes());
+        }
+    }
+
+    if let Some(name) = &meta.groupname {
+        header.set_groupname(truncate_name(name))?;
+        if name.len() >= MAX_NAME_LEN {
+            format.extend(&mut ext, b"gname", name.as_bytes());
+        }
+    }
+
+    match meta.mtime.duration_since(UNIX_EPOCH) {
+        Ok(mtime) => {
+            header.set_mtime(mtime.as_secs());
+            if mtime.as_secs() > MAX_OCTAL_12 || mtime.subsec_nanos() > 0 {
+                format.extend(&mut ext, b"mtime", format_time(meta.mtime).as_bytes());
+            }
+        }
+        Err(err) => {
+            // Times before the epoch can only be represented in base-256,
+            // rounded down to the second.
+            let before = err.duration();
+            let secs = before.as_secs() + u64::from(before.subsec_nanos() > 0);
+            let secs = i64::try_from(secs).map_or(i64::MIN, |secs| -secs);
+            set_header_mtime(&mut header, secs);
+            format.extend(&mut ext, b"mtime", format_time(meta.mtime).as_bytes());
+        }
+    }
+
+    // The GNU header has fields for these, in place of the ustar prefix.
+    for (key, time) in [(b"atime", meta.atime), (b"ctime", meta.ctime)] {
+        let Some(time) = time else {
+            continue;
+        };
+        match (format, header.as_gnu_mut()) {
+            // Times before the epoch are left out.
+            (Format::Gnu, Some(gnu)) => {
+                if let Ok(time) = time.duration_since(UNIX_EPOCH) {
+                    match key {
+                        b"atime" => gnu.set_atime(time.as_secs()),
+                        _ => gnu.set_ctime(time.as_secs()),
+                    }
+                }
+            }
+            _ => format.extend(&mut ext, key, format_time(time).as_bytes()),
+        }
+    }
+
+    if let Some((major, minor)) = meta.device {
+        header.set_device_major(major)?;
+        header.set_device_minor(minor)?;
+    }
+
+    header.set_cksum();
+
+    Ok((header, ext))
+}
+
+/// Builds a finalized header for a sparse entry with the given data regions
+/// and file size, along with its extensions and the PAX 1.0 sparse map that
+/// precedes its data. Sparse entries always use PAX, whatever the format.
+pub fn encode_sparse(
+    path: &Path,
+    regions: &[SparseRegion],
+    size: u64,
+    meta: &EntryMetadata,
+) -> Result<(Header, EntryExtensions, Vec<u8>)> {
+    let name = normalize(path)?;
+    let data_len = regions
+        .iter()
+        .try_fold(0u64, |len, region| len.checked_add(region.len))
+        .ok_or_else(|| IoError::new(ErrorKind::InvalidInput, "sparse regions too large"))?;
+    let mut sparse = Sparse::new(regions.to_vec(), size, Some(name.into()));
+    sparse
+        .validate(data_len)
+        .map_err(|err| IoError::new(ErrorKind::InvalidInput, err.to_string()))?;
+    let map = sparse.encode_pax_map();
+
+    // Like GNU tar, store the entry under a made-up path so that readers
+    // that don't understand sparse files don't extract the map and data in
+    // place of the file.
+    let file_name = path.file_name().unwrap_or_default();
+    let parent = path.parent().unwrap_or(Path::new(""));
+    let fake_path = parent.join("GNUSparseFile.0").join(file_name);
+
+    let (header, mut ext) = encode(&fake_path, sparse.map_len + data_len, meta, Format::Pax)?;
+    sparse.to_pax_attributes(&mut ext.pax);
+    ext.sparse = Some(sparse);
+    Ok((header, ext, map))
+}
+
+/// Builds the finalized headers and data of the extension entries that must
+/// precede the entry with the given header, in order.
+pub fn extension_entries(header: &Header, ext: &EntryExtensions) -> Vec<(Header, Vec<u8>)> {
+    let mut entries = Vec::new();
+
+    if !ext.pax.is_empty() {
+        let data = ext.pax.to_bytes();
+        let mut pax = Header::new_ustar();
+        pax.set_entry_type(EntryType::XHeader);
+        pax.set_mode(0o644);
+        pax.set_mtime(header.mtime().unwrap_or(0));
+        pax.set_size(data.len() as u64);
+
+        // Name the extended header after the entry, like GNU tar does.
+        let path = header.path_bytes();
+        let name = path.rsplit(|b| *b == b'/').find(|s| !s.is_empty());
+        let name = [b"PaxHeaders/", name.unwrap_or_default()].concat();
+        set_truncated(&mut pax.as_old_mut().name, &name);
+
+        pax.set_cksum();
+        entries.push((pax, data));
+    }
+
+    for (kind, name) in [
+        (EntryType::GNULongName, &ext.long_name),
+        (EntryType::GNULongLink, &ext.long_link),
+    ] {
+        let Some(name) = name else {
+            continue;
+        };
+        // Long names are NUL-terminated.
+        let data = [name, &b"\0"[..]].concat();
+        let mut gnu = Header::new_gnu();
+        gnu.set_entry_type(kind);
+        gnu.set_mode(0o644);
+        gnu.set_uid(0);
+        gnu.set_gid(0);
+        gnu.set_mtime(0);
+        gnu.set_size(data.len() as u64);
+        set_truncated(&mut gnu.as_old_mut().name, GNU_LONG_LINK_PATH.as_bytes());
+        gnu.set_cksum();
+        entries.push((gnu, data));
+    }
+
+    entries
+}
+
+/// Converts `path` to the bytes to be stored in an archive, making sure it
+/// is relative and does not escape the archive root.
+fn normalize(path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => {
+                if !bytes.is_empty() {
+                    bytes.push(b'/');
+                }
+                bytes.extend_from_slice(&path_bytes(Path::new(part))?);
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                return Err(invalid_path("paths in archives must not have `..`"));
+            }
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(invalid_path("paths in archives must be relative"));
+            }
+        }
+    }
+    if bytes.is_empty() {
+        return Err(invalid_path("paths in archives must not be empty"));
+    }
+    Ok(bytes)
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Result<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(path.as_os_str().as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Result<Vec<u8>> {
+    path.to_str()
+        .map(|s| s.replace('\\', "/").into_bytes())
+        .ok_or_else(|| invalid_path("path is not valid unicode"))
+}
+
+/// Truncates a user or group name to fit in the header, at a character
+/// boundary.
+fn truncate_name(name: &str) -> &str {
+    let mut len = name.len().min(MAX_NAME_LEN - 1);
+    while !name.is_char_boundary(len) {
+        len -= 1;
+    }
+    &name[..len]
+}
+
+fn set_truncated(field: &mut [u8], value: &[u8]) {
+    let len = value.len().min(field.len());
+    field.fill(0);
+    field[..len].copy_from_slice(&value[..len]);
+}
+
+fn invalid_path(msg: &str) -> IoError {
+    IoError::new(ErrorKind::InvalidInput, msg)
+}
@@ -1,5 +1,6 @@
 use std::io::{IoSlice, Result};
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll, ready};
 
 use tokio::io::AsyncWrite;
@@ -11,15 +12,40 @@ use crate::shared::state::State;
 
 use crate::{Archive, Entry, TRACING_ENABLED};
 
+#[cfg(feature = "compression")]
+mod encode;
+#[cfg(feature = "compression")]
+pub use self::encode::Encoder;
+
 mod error;
 pub use self::error::WriteError;
 
+mod builder;
+pub use self::builder::HeaderBuilder;
+
+mod ext;
+pub use self::ext::{EntryMetadata, Format};
+pub(crate) use self::ext::{encode, encode_sparse, extension_entries};
+
+mod reproducible;
+pub(crate) use self::reproducible::normalize;
+
+#[cfg(feature = "fs")]
+mod append;
+#[cfg(feature = "fs")]
+pub(crate) use self::append::{AppendOptions, append_dir_all};
+#[cfg(not(feature = "fs"))]
+pub(crate) type AppendOptions = ();
+
 impl<W: AsyncWrite> Archive<W> {
     pub(super) fn poll_write_header(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         header: &Header,
     ) -> Poll<Result<()>> {
+        self.check_poison()?;
+        let len = header.entry_size()?;
+
         loop {
             if TRACING_ENABLED {
                 eprintln!("     |whead: {:?}", self.state);
@@ -28,7 +54,7 @@ impl<W: AsyncWrite> Archive<W> {
             match self.state {
                 State::ExpectingHeader => {
                     let buf = header.as_bytes();
-                    let n = ready!(self.as_mut().poll_write_data(cx, buf, Some(header)))?;
+                    let n = ready!(self.as_mut().poll_write_data(cx, buf, Some(len)))?;
                     if n == 0 {
                         return WriteError::WriteZero.into();
                     }
@@ -38,7 +64,7 @@ impl<W: AsyncWrite> Archive<W> {
                 State::ReceivingHeader(rem, false) => {
                     let pos = BLOCK_SIZE - rem;
                     let buf = &header.as_bytes()[pos..];
-                    let n = ready!(self.as_mut().poll_write_data(cx, buf, Some(header)))?;
+                    let n = ready!(self.as_mut().poll_write_data(cx, buf, Some(len)))?;
                     if n == 0 && rem > 0 {
                         return WriteError::WriteZero.into();
                     }
@@ -46,19 +72,30 @@ impl<W: AsyncWrite> Archive<W> {
                 }
 
                 State::ReceivedHeader => {
-                    self.project().state.take_marker(Some(header))?;
+                    let this = self.as_mut().project();
+                    this.state.take_marker(Some(len))?;
+                    if len == 0 {
+                        // Entries with no data are complete as soon as their
+                        // header is written.
+                        *this.state = this.state.next(&[], Some(len))?.0;
+                        return self.poll_finish_entry(cx, len);
+                    }
                     return Poll::Ready(Ok(()));
                 }
 
-                State::ReceivingData(_)
-                | State::ReceivedData
-                | State::AligningData(_)
-                | State::AlignedData => {
-                    return WriteError::OverlappingEntry.into();
+                State::AligningData(_) | State::AlignedData => {
+                    // Finishing off the alignment of a previous entry, which
+                    // doesn't depend on its length.
+                    ready!(self.as_mut().poll_finish_entry(cx, 0))?;
+                    continue;
+                }
+
+                State::ReceivingData(_) | State::ReceivedData => {
+                    return Poll::Ready(Err(self.poison(WriteError::OverlappingEntry)));
                 }
 
                 State::ReceivingHeader(_, true) | State::ReceivingEof(_) | State::ReceivedEof => {
-                    panic!("cannot write header; invalid state: {:?}", self.state)
+                    return Poll::Ready(Err(self.poison(WriteError::Finished)));
                 }
             }
         }
@@ -68,38 +105,89 @@ impl<W: AsyncWrite> Archive<W> {
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         bufs: &[IoSlice<'_>],
-        header: &Header,
+        len: u64,
     ) -> Poll<Result<usize>> {
         if TRACING_ENABLED {
             eprintln!("     |write: {:?}", self.state);
         }
 
+        self.check_poison()?;
+        if bufs.bytes_len() == 0 {
+            return Poll::Ready(Ok(0));
+        }
+
         match self.state {
             State::ReceivingData(rem) => {
-                let n = ready!(self.as_mut().poll_write_vectored(
-                    cx,
-                    bufs,
-                    rem as usize,
-                    Some(header)
-                ))?;
+                let max = usize::try_from(rem).unwrap_or(usize::MAX);
+                let n = ready!(self.as_mut().poll_write_vectored(cx, bufs, max, Some(len)))?;
                 if n as u64 == rem {
                     debug_assert_eq!(bufs.bytes_len(), n);
                     debug_assert_eq!(self.state, State::ReceivedData);
-                    let res = ready!(self.poll_finish_entry(cx, header));
-                    debug_assert!(res.is_ok());
+                    // The data has been taken, so alignment that cannot be
+                    // written yet is left for whatever is written next, and
+                    // errors writing it are left for that to report.
+                    if let Poll::Ready(Err(err)) = self.as_mut().poll_finish_entry(cx, len)
+                        && self.check_poison().is_ok()
+                    {
+                        self.as_mut().poison(WriteError::Padding(Arc::new(err)));
+                    }
                 }
                 Poll::Ready(Ok(n))
             }
 
+            // The entry is finished as soon as its data is written.
             State::ExpectingHeader
-            | State::ReceivingHeader(_, _)
-            | State::ReceivedHeader
             | State::ReceivedData
             | State::AligningData(_)
-            | State::AlignedData
-            | State::ReceivingEof(_)
-            | State::ReceivedEof => {
-                panic!("cannot write entry; invalid state: {:?}", self.state)
+            | State::AlignedData => Poll::Ready(Err(self.poison(WriteError::EntryOverflow))),
+
+            State::ReceivingHeader(_, false) | State::ReceivedHeader => {
+                Poll::Ready(Err(self.poison(WriteError::IncompleteHeader)))
+            }
+
+            State::ReceivingHeader(_, true) | State::ReceivingEof(_) | State::ReceivedEof => {
+                Poll::Ready(Err(self.poison(WriteError::Finished)))
+            }
+        }
+    }
+
+    /// Writes the data of an extension entry, after its header has been
+    /// written with [Self::poll_write_header], and finishes the entry.
+    pub(super) fn poll_write_extension(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<Result<()>> {
+        self.check_poison()?;
+        let len = data.len() as u64;
+
+        loop {
+            match self.state {
+                State::ReceivingData(rem) => {
+                    let pos = (len - rem) as usize;
+                    let bufs = [IoSlice::new(&data[pos..])];
+                    let n = ready!(self.as_mut().poll_write_entry(cx, &bufs, len))?;
+                    if n == 0 {
+                        return WriteError::WriteZero.into();
+                    }
+                    continue;
+                }
+
+                State::ReceivedData | State::AligningData(_) | State::AlignedData => {
+                    return self.poll_finish_entry(cx, len);
+                }
+
+                State::ExpectingHeader => {
+                    return Poll::Ready(Ok(()));
+                }
+
+                State::ReceivingHeader(_, false) | State::ReceivedHeader => {
+                    return Poll::Ready(Err(self.poison(WriteError::IncompleteHeader)));
+                }
+
+                State::ReceivingHeader(_, true) | State::ReceivingEof(_) | State::ReceivedEof => {
+                    return Poll::Ready(Err(self.poison(WriteError::Finished)));
+                }
             }
         }
     }
@@ -107,8 +195,10 @@ impl<W: AsyncWrite> Archive<W> {
     fn poll_finish_entry(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
-        header: &Header,
+        len: u64,
     ) -> Poll<Result<()>> {
+        self.check_poison()?;
+
         loop {
             if TRACING_ENABLED {
                 eprintln!("     | fini: {:?}", self.state);
@@ -116,13 +206,13 @@ impl<W: AsyncWrite> Archive<W> {
 
             match self.state {
                 State::ReceivedData => {
-                    self.as_mut().project().state.take_marker(Some(header))?;
+                    self.as_mut().project().state.take_marker(Some(len))?;
                     continue;
                 }
 
                 State::AligningData(rem) => {
                     let buf = &Block::empty().as_bytes()[..rem];
-                    ready!(self.as_mut().poll_write_data(cx, buf, Some(header)))?;
+                    ready!(self.as_mut().poll_write_data(cx, buf, Some(len)))?;
                     continue;
                 }
 
@@ -135,18 +225,28 @@ impl<W: AsyncWrite> Archive<W> {
                     return Poll::Ready(Ok(()));
                 }
 
-                State::ReceivingHeader(_, _)
-                | State::ReceivedHeader
-                | State::ReceivingData(_)
-                | State::ReceivingEof(_)
-                | State::ReceivedEof => {
-                    panic!("cannot finish entry; invalid state: {:?}", self.state)
+                State::ReceivingData(rem) => {
+                    let err = WriteError::UnexpectedEof {
+                        expected: len,
+                        received: len - rem,
+                    };
+                    return Poll::Ready(Err(self.poison(err)));
+                }
+
+                State::ReceivingHeader(_, false) | State::ReceivedHeader => {
+                    return Poll::Ready(Err(self.poison(WriteError::IncompleteHeader)));
+                }
+
+                State::ReceivingHeader(_, true) | State::ReceivingEof(_) | State::ReceivedEof => {
+                    return Poll::Ready(Err(self.poison(WriteError::Finished)));
                 }
             }
         }
     }
 
     pub(super) fn poll_finish(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.check_poison()?;
+
         loop {
             match self.state {
                 State::ExpectingHeader => {
@@ -166,13 +266,17 @@ impl<W: AsyncWrite> Archive<W> {
                     return self.project().io.poll_shutdown(cx);
                 }
 
-                State::ReceivingHeader(_, false)
-                | State::ReceivedHeader
-                | State::ReceivingData(_)
-                | State::ReceivedData
-                | State::AligningData(_)
-                | State::AlignedData => {
-                    panic!("cannot finish archive; invalid state: {:?}", self.state)
+                State::ReceivingHeader(_, false) | State::ReceivedHeader => {
+                    return Poll::Ready(Err(self.poison(WriteError::IncompleteHeader)));
+                }
+
+                State::AligningData(_) | State::AlignedData => {
+                    ready!(self.as_mut().poll_finish_entry(cx, 0))?;
+                    continue;
+                }
+
+                State::ReceivingData(_) | State::ReceivedData => {
+                    return Poll::Ready(Err(self.poison(WriteError::UnfinishedEntry)));
                 }
             }
         }
@@ -180,7 +284,10 @@ impl<W: AsyncWrite> Archive<W> {
 
     /// Send data in our main buffer into the inner writer, looping as
     /// necessary until either it's all been sent or an error occurs.
-    fn poll_flush_buffered(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+    pub(super) fn poll_flush_buffered(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<()>> {
         let mut this = self.project();
         let mut buf = this.buf.buffered();
 
@@ -206,11 +313,11 @@ impl<W: AsyncWrite> Archive<W> {
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
         buf: &[u8],
-        header: Option<&Header>,
+        len: Option<u64>,
     ) -> Poll<Result<usize>> {
         let max = buf.len();
         let slice = [IoSlice::new(buf)];
-        self.poll_write_vectored(cx, &slice, max, header)
+        self.poll_write_vectored(cx, &slice, max, len)
     }
 
     fn poll_write_vectored(
@@ -218,7 +325,7 @@ impl<W: AsyncWrite> Archive<W> {
         cx: &mut Context<'_>,
         bufs: &[IoSlice<'_>],
         max: usize,
-        header: Option<&Header>,
+        len: Option<u64>,
     ) -> Poll<Result<usize>> {
         let prefix = bufs.take_prefix(max);
         let prefix_len = prefix.bytes_len();
@@ -226,7 +333,7 @@ impl<W: AsyncWrite> Archive<W> {
         // Check that bufs contain valid data before we go ahead and write them.
         let next = {
             let this = self.as_mut().project();
-            this.state.take_slices(prefix.iter_buffers(), header)?
+            this.state.take_slices(prefix.iter_buffers(), len)?
         };
         assert_eq!(next.1, prefix_len);
 
@@ -267,6 +374,8 @@ impl<W: AsyncWrite> Archive<W> {
             this.buf.available().fill_from_slices(prefix.iter_buffers())
         };
 
+        *this.pos += bytes_written as u64;
+
         // Update our state based on the actual slice of data that we've written.
         *this.state = if bytes_written == prefix_len {
             next.0
@@ -275,7 +384,7 @@ impl<W: AsyncWrite> Archive<W> {
             // This cannot fail because we've already checked every slice within bufs.
             let next = this
                 .state
-                .take_slices(prefix.iter_buffers(), header)
+                .take_slices(prefix.iter_buffers(), len)
                 .expect("this slice should have already been checked");
             assert_eq!(next.1, bytes_written);
             next.0
@@ -284,7 +393,7 @@ impl<W: AsyncWrite> Archive<W> {
         Poll::Ready(Ok(bytes_written))
     }
 
-    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+    pub(super) fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
         ready!(self.as_mut().poll_flush_buffered(cx))?;
         self.project().io.poll_flush(cx)
     }
@@ -309,8 +418,8 @@ impl<W: AsyncWrite> AsyncWrite for Entry<'_, W> {
             eprintln!("write: '{}', size = {}", self.path_lossy(), self.size());
         }
         let this = self.project();
-        let header = this.header;
-        this.archive.as_mut().poll_write_entry(cx, bufs, header)
+        let len = *this.len;
+        this.archive.as_mut().poll_write_entry(cx, bufs, len)
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
@@ -322,8 +431,8 @@ impl<W: AsyncWrite> AsyncWrite for Entry<'_, W> {
             eprintln!("finsh: '{}', size = {}", self.path_lossy(), self.size());
         }
         let this = self.project();
-        let header = this.header;
-        ready!(this.archive.as_mut().poll_finish_entry(cx, header))?;
+        let len = *this.len;
+        ready!(this.archive.as_mut().poll_finish_entry(cx, len))?;
         this.archive.as_mut().poll_flush(cx)
     }
 
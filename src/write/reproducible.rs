@@ -0,0 +1,66 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::shared::block::{EntryType, Header, header_mtime, set_header_mtime};
+use crate::shared::ext::EntryExtensions;
+
+use super::WriteError;
+
+/// PAX records that vary with where and when an archive is written.
+const VOLATILE_RECORDS: [&[u8]; 6] = [b"uid", b"gid", b"uname", b"gname", b"atime", b"ctime"];
+
+/// Normalises the header and extensions of an entry so that entries for the
+/// same files come out the same wherever and whenever they're written:
+/// mtimes later than `epoch` are clamped to it, owners and access and
+/// change times are cleared, and modes are reduced to 0o755 or 0o644.
+pub fn normalize(
+    header: &mut Header,
+    ext: &mut EntryExtensions,
+    epoch: SystemTime,
+) -> Result<(), WriteError> {
+    // Clamped times are rounded down to the second so they fit the header.
+    let secs = match epoch.duration_since(UNIX_EPOCH) {
+        Ok(since) => i64::try_from(since.as_secs()).unwrap_or(i64::MAX),
+        Err(err) => {
+            let before = err.duration();
+            let secs = before.as_secs() + u64::from(before.subsec_nanos() > 0);
+            i64::try_from(secs).map_or(i64::MIN, |secs| -secs)
+        }
+    };
+    let clamp = match ext.pax.mtime().map_err(|_| WriteError::InvalidMtime)? {
+        Some(mtime) => mtime > epoch,
+        None => header_mtime(header).map_err(|_| WriteError::InvalidMtime)? > secs,
+    };
+    if clamp {
+        ext.pax.remove(b"mtime");
+        set_header_mtime(header, secs);
+    }
+
+    header.set_uid(0);
+    header.set_gid(0);
+    for key in VOLATILE_RECORDS {
+        ext.pax.remove(key);
+    }
+    if let Some(ustar) = header.as_ustar_mut() {
+        ustar.uname.fill(0);
+        ustar.gname.fill(0);
+    }
+    if let Some(gnu) = header.as_gnu_mut() {
+        gnu.uname.fill(0);
+        gnu.gname.fill(0);
+        gnu.atime.fill(0);
+        gnu.ctime.fill(0);
+    }
+
+    // The mode is replaced anyway, so an empty one will do.
+    let mode = header.mode().unwrap_or(0);
+    let mode = match header.entry_type() {
+        EntryType::Symlink => 0o777,
+        EntryType::Directory => 0o755,
+        _ if mode & 0o111 != 0 => 0o755,
+        _ => 0o644,
+    };
+    header.set_mode(mode);
+
+    header.set_cksum();
+    Ok(())
+}
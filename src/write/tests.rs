@@ -3,8 +3,9 @@ use std::num::NonZeroUsize;
 
 use tokio::io::{AsyncWrite, AsyncWriteExt};
 
-use crate::Archive;
+use crate::shared::block::BLOCK_SIZE;
 use crate::shared::test::*;
+use crate::{Archive, WriteError};
 
 const FILES: [(&str, usize); 4] = [("512", 512), ("1024", 1024), ("500", 500), ("1000", 1000)];
 
@@ -61,3 +62,909 @@ async fn overlapping_entries() {
         assert_eq!(res.unwrap_err().kind(), io::ErrorKind::Unsupported);
     }
 }
+
+fn write_error(err: io::Error) -> WriteError {
+    *err.into_inner().unwrap().downcast().unwrap()
+}
+
+#[tokio::test]
+async fn misuse() {
+    let header = make_entry_header("a", 10);
+
+    // Finishing an entry early leaves the archive poisoned.
+    let mut archive = Archive::new(Vec::new());
+    let mut entry = archive.add_entry(header.clone()).await.unwrap();
+    entry.write_all(&[1; 5]).await.unwrap();
+    let err = write_error(entry.finish().await.unwrap_err());
+    assert!(matches!(
+        err,
+        WriteError::UnexpectedEof {
+            expected: 10,
+            received: 5
+        }
+    ));
+    let err = archive.finish().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    let err = write_error(err);
+    assert!(
+        matches!(&err, WriteError::Poisoned(cause) if matches!(**cause, WriteError::UnexpectedEof { .. }))
+    );
+    assert!(std::error::Error::source(&err).is_some());
+
+    // Finishing the archive while an entry is being written.
+    let mut archive = Archive::new(Vec::new());
+    let mut entry = archive.add_entry(header.clone()).await.unwrap();
+    entry.write_all(&[1; 5]).await.unwrap();
+    let err = write_error(archive.finish().await.unwrap_err());
+    assert!(matches!(err, WriteError::UnfinishedEntry));
+    let err = write_error(archive.add_entry(header.clone()).await.unwrap_err());
+    assert!(matches!(err, WriteError::Poisoned(_)));
+
+    // Writing past the end of an entry.
+    let mut archive = Archive::new(Vec::new());
+    let mut entry = archive.add_entry(header.clone()).await.unwrap();
+    entry.write_all(&[1; 10]).await.unwrap();
+    let err = write_error(entry.write(&[1]).await.unwrap_err());
+    assert!(matches!(err, WriteError::EntryOverflow));
+
+    // Writing to a finished archive.
+    let mut archive = Archive::new(Vec::new());
+    archive.finish().await.unwrap();
+    let err = write_error(archive.add_entry(header.clone()).await.unwrap_err());
+    assert!(matches!(err, WriteError::Finished));
+    let err = write_error(archive.finish().await.unwrap_err());
+    assert!(matches!(err, WriteError::Poisoned(_)));
+
+    // Adding an entry whose header checksum was never set, in which case
+    // nothing is written.
+    for cksum in [[0; 8], [b' '; 8]] {
+        let mut header = header.clone();
+        header.as_old_mut().cksum = cksum;
+        let mut archive = Archive::new(Vec::new());
+        let err = write_error(archive.add_entry(header).await.unwrap_err());
+        assert!(matches!(err, WriteError::UnfinalizedHeader));
+        let err = write_error(archive.finish().await.unwrap_err());
+        assert!(
+            matches!(&err, WriteError::Poisoned(cause) if matches!(**cause, WriteError::UnfinalizedHeader))
+        );
+        assert!(archive.into_inner().is_empty());
+    }
+}
+
+#[tokio::test]
+async fn padding_error() {
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Takes at most 700 bytes at a time and fails after 1212 bytes.
+    struct FailingWriter(usize);
+
+    impl AsyncWrite for FailingWriter {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let n = buf.len().min(700).min(1212 - self.0);
+            if n == 0 && !buf.is_empty() {
+                return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+            }
+            self.0 += n;
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn is_write_vectored(&self) -> bool {
+            true
+        }
+    }
+
+    // The data is passed through in part, which leaves the padding too
+    // long for what is left of the buffer, and it fails to be flushed
+    // after the data was reported as written.
+    let mut archive = Archive::with_capacity(FailingWriter(0), NonZeroUsize::new(1).unwrap());
+    let mut entry = archive
+        .add_entry(make_entry_header("a", 1100))
+        .await
+        .unwrap();
+    let data = make_entry_data(1100);
+    assert_eq!(entry.write(&data[..1100]).await.unwrap(), 700);
+    assert_eq!(entry.write(&data[700..1100]).await.unwrap(), 400);
+
+    let err = archive.finish().await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    let err = write_error(err);
+    assert!(
+        matches!(&err, WriteError::Poisoned(cause) if matches!(**cause, WriteError::Padding(_)))
+    );
+}
+
+#[tokio::test]
+async fn pax_extended_headers() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use tokio::io::AsyncReadExt;
+
+    use crate::{EntryMetadata, EntryType};
+
+    let long_path = "long/".repeat(40) + "path";
+    let long_link = "link/".repeat(40) + "target";
+    let mtime = UNIX_EPOCH + Duration::new(1_700_000_000, 250_000_000);
+    let meta = EntryMetadata {
+        uid: 1 << 32,
+        username: Some("u".repeat(40)),
+        mtime,
+        ..Default::default()
+    };
+    let link_meta = EntryMetadata {
+        kind: EntryType::Symlink,
+        link_name: Some(long_link.clone().into()),
+        ..Default::default()
+    };
+
+    for cap in [1, 10] {
+        eprintln!("cap = {cap}");
+
+        let mut io: Vec<u8> = Vec::new();
+        let mut archive = Archive::with_capacity(&mut io, NonZeroUsize::new(cap).unwrap());
+
+        let data = make_entry_data(1000);
+        let mut entry = archive
+            .add_entry_with_metadata(&long_path, 1000, &meta)
+            .await
+            .unwrap();
+        assert_eq!(entry.path_lossy(), long_path);
+        entry.write_all(&data[..1000]).await.unwrap();
+
+        let entry = archive
+            .add_entry_with_metadata("short", 0, &link_meta)
+            .await
+            .unwrap();
+        assert_eq!(entry.link_name().unwrap().as_ref(), long_link.as_bytes());
+
+        archive.finish().await.unwrap();
+
+        // Check that we can read back what we wrote...
+        let mut archive = Archive::new(io.as_slice());
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), long_path);
+        assert_eq!(entry.uid().unwrap(), 1 << 32);
+        assert_eq!(entry.username(), Some("u".repeat(40).as_bytes()));
+        assert_eq!(entry.mtime().unwrap(), mtime);
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, &data[..1000]);
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), "short");
+        assert_eq!(entry.link_name().unwrap().as_ref(), long_link.as_bytes());
+        entry.skip().await.unwrap();
+        assert!(archive.next_entry().await.unwrap().is_none());
+
+        // ...and so can others.
+        let mut archive = tar::Archive::new(io.as_slice());
+        let paths = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, [long_path.as_str(), "short"]);
+    }
+}
+
+#[tokio::test]
+async fn pax_not_needed() {
+    use crate::EntryMetadata;
+
+    let data = make_archive_data(&FILES);
+
+    let mut io: Vec<u8> = Vec::new();
+    let mut archive = Archive::new(&mut io);
+    let meta = EntryMetadata::default();
+    for (path, size) in FILES.iter() {
+        let mut entry = archive
+            .add_entry_with_metadata(path, *size as u64, &meta)
+            .await
+            .unwrap();
+        assert!(entry.pax_attributes().is_empty());
+        entry
+            .write_all(&make_entry_data(*size)[..*size])
+            .await
+            .unwrap();
+    }
+    archive.finish().await.unwrap();
+
+    // No extended headers are written.
+    assert_eq!(io.len(), data.len());
+}
+
+#[tokio::test]
+async fn invalid_paths() {
+    use crate::EntryMetadata;
+
+    let mut io: Vec<u8> = Vec::new();
+    let mut archive = Archive::new(&mut io);
+    let meta = EntryMetadata::default();
+    for path in ["/abs", "a/../b", "", "."] {
+        let res = archive.add_entry_with_metadata(path, 0, &meta).await;
+        assert_eq!(res.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[tokio::test]
+async fn pax_large_size() {
+    use crate::EntryMetadata;
+
+    let size = 10 << 30;
+    let mut io: Vec<u8> = Vec::new();
+    let mut archive = Archive::new(&mut io);
+    let entry = archive
+        .add_entry_with_metadata("big", size, &EntryMetadata::default())
+        .await
+        .unwrap();
+    assert_eq!(entry.size(), size);
+    assert_eq!(entry.len(), size);
+    assert_eq!(entry.pax_attributes().size().unwrap(), Some(size));
+    assert_eq!(entry.header().size().unwrap(), size);
+}
+
+#[tokio::test]
+async fn gnu_long_names() {
+    use tokio::io::AsyncReadExt;
+
+    use crate::{EntryMetadata, EntryType, Format, Header};
+
+    let long_path = "long/".repeat(40) + "path";
+    let long_link = "link/".repeat(40) + "target";
+    let meta = EntryMetadata {
+        uid: 1 << 32,
+        ..Default::default()
+    };
+    let link_meta = EntryMetadata {
+        kind: EntryType::Symlink,
+        link_name: Some(long_link.clone().into()),
+        ..Default::default()
+    };
+
+    for cap in [1, 10] {
+        eprintln!("cap = {cap}");
+
+        let mut io: Vec<u8> = Vec::new();
+        let mut archive = Archive::with_capacity(&mut io, NonZeroUsize::new(cap).unwrap());
+        archive.set_format(Format::Gnu);
+
+        let data = make_entry_data(1000);
+        let mut entry = archive
+            .add_entry_with_metadata(&long_path, 1000, &meta)
+            .await
+            .unwrap();
+        assert_eq!(entry.path_lossy(), long_path);
+        assert!(entry.pax_attributes().is_empty());
+        entry.write_all(&data[..1000]).await.unwrap();
+
+        archive
+            .add_entry_with_metadata(&long_path, 0, &link_meta)
+            .await
+            .unwrap();
+
+        archive.finish().await.unwrap();
+
+        let header = Header::from_byte_slice(&io[..BLOCK_SIZE]);
+        assert!(header.entry_type().is_gnu_longname());
+
+        let mut archive = Archive::new(io.as_slice());
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), long_path);
+        assert_eq!(entry.uid().unwrap(), 1 << 32);
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, &data[..1000]);
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), long_path);
+        assert_eq!(entry.link_name().unwrap().as_ref(), long_link.as_bytes());
+        entry.skip().await.unwrap();
+        assert!(archive.next_entry().await.unwrap().is_none());
+
+        let mut archive = tar::Archive::new(io.as_slice());
+        for entry in archive.entries().unwrap() {
+            let entry = entry.unwrap();
+            assert_eq!(entry.path().unwrap().to_string_lossy(), long_path);
+        }
+    }
+}
+
+#[tokio::test]
+async fn sparse_entries() {
+    use tokio::io::AsyncReadExt;
+
+    use crate::{EntryMetadata, SparseRegion};
+
+    let regions = [
+        SparseRegion {
+            offset: 100,
+            len: 1000,
+        },
+        SparseRegion {
+            offset: 5000,
+            len: 10,
+        },
+    ];
+    let size = 1 << 20;
+    let data = make_entry_data(1010);
+    let meta = EntryMetadata::default();
+
+    for cap in [1, 10] {
+        eprintln!("cap = {cap}");
+
+        let mut io: Vec<u8> = Vec::new();
+        let mut archive = Archive::with_capacity(&mut io, NonZeroUsize::new(cap).unwrap());
+
+        let mut entry = archive
+            .add_sparse_entry("disk/image", &regions, size, &meta)
+            .await
+            .unwrap();
+        assert_eq!(entry.path_lossy(), "disk/image");
+        assert_eq!(entry.size(), size);
+        assert_eq!(entry.len(), (BLOCK_SIZE + 1010) as u64);
+        entry.write_all(&data[..1010]).await.unwrap();
+
+        // All holes.
+        archive
+            .add_sparse_entry("empty", &[], size, &meta)
+            .await
+            .unwrap();
+
+        archive.finish().await.unwrap();
+
+        // Each entry is a PAX header, a header, the map and the regions,
+        // followed by the EOF blocks.
+        assert_eq!(io.len(), (6 + 4 + 2) * BLOCK_SIZE);
+
+        let mut archive = Archive::new(io.as_slice());
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), "disk/image");
+        assert_eq!(
+            entry.header().path_bytes().as_ref(),
+            b"disk/GNUSparseFile.0/image"
+        );
+        assert_eq!(entry.sparse_map(), Some(&regions[..]));
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await.unwrap();
+        let mut expected = vec![0u8; size as usize];
+        expected[100..1100].copy_from_slice(&data[..1000]);
+        expected[5000..5010].copy_from_slice(&data[1000..1010]);
+        assert_eq!(buf, expected);
+
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.path_lossy(), "empty");
+        assert_eq!(entry.sparse_map(), Some(&[][..]));
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(buf, vec![0u8; size as usize]);
+
+        assert!(archive.next_entry().await.unwrap().is_none());
+    }
+
+    let mut io: Vec<u8> = Vec::new();
+    let mut archive = Archive::new(&mut io);
+    for regions in [
+        &[regions[1], regions[0]][..],
+        &[SparseRegion { offset: 0, len: 10 }],
+    ] {
+        let err = archive
+            .add_sparse_entry("bad", regions, 5, &meta)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
+
+#[tokio::test]
+async fn gnu_base256_fields() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::{EntryMetadata, Format};
+
+    let size = 10 << 30;
+    let meta = EntryMetadata {
+        uid: 1 << 32,
+        gid: 1 << 22,
+        mtime: UNIX_EPOCH + Duration::from_secs(1 << 40),
+        ..Default::default()
+    };
+
+    let mut io: Vec<u8> = Vec::new();
+    let mut archive = Archive::new(&mut io);
+    archive.set_format(Format::Gnu);
+    let entry = archive
+        .add_entry_with_metadata("big", size, &meta)
+        .await
+        .unwrap();
+    assert!(entry.pax_attributes().is_empty());
+    assert_eq!(entry.size(), size);
+    assert_eq!(entry.len(), size);
+    assert_eq!(entry.uid().unwrap(), meta.uid);
+    assert_eq!(entry.gid().unwrap(), meta.gid);
+    assert_eq!(entry.mtime().unwrap(), meta.mtime);
+
+    let header = entry.header().as_old();
+    for field in [&header.size[..], &header.uid, &header.gid, &header.mtime] {
+        assert_eq!(field[0], 0x80);
+    }
+}
+
+#[tokio::test]
+async fn gnu_negative_mtime() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::{EntryMetadata, Format};
+
+    let meta = EntryMetadata {
+        mtime: UNIX_EPOCH - Duration::new(10, 500_000_000),
+        ..Default::default()
+    };
+
+    let mut io: Vec<u8> = Vec::new();
+    let mut archive = Archive::new(&mut io);
+    archive.set_format(Format::Gnu);
+    let entry = archive
+        .add_entry_with_metadata("old", 0, &meta)
+        .await
+        .unwrap();
+    assert_eq!(entry.mtime().unwrap(), UNIX_EPOCH - Duration::from_secs(11));
+    assert_eq!(entry.header().as_old().mtime[0], 0xff);
+}
+
+#[cfg(all(unix, feature = "fs"))]
+#[tokio::test]
+async fn append_dir_all() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt, symlink};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use tokio::io::AsyncReadExt;
+
+    use crate::EntryType;
+
+    let root = std::env::temp_dir().join(format!("tario-append-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    let src = root.join("src");
+    std::fs::create_dir_all(src.join("b")).unwrap();
+    std::fs::write(src.join("a.txt"), b"hello").unwrap();
+    std::fs::write(src.join("b/c.txt"), b"world!").unwrap();
+    std::fs::hard_link(src.join("a.txt"), src.join("b/h")).unwrap();
+    symlink("a.txt", src.join("l")).unwrap();
+    std::fs::set_permissions(src.join("b/c.txt"), PermissionsExt::from_mode(0o600)).unwrap();
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    std::fs::File::open(src.join("b/c.txt"))
+        .unwrap()
+        .set_modified(mtime)
+        .unwrap();
+    let uid = std::fs::metadata(&src).unwrap().uid();
+
+    async fn list(data: &[u8]) -> Vec<(String, EntryType, String, String)> {
+        let mut archive = Archive::new(io::Cursor::new(data));
+        let mut entries = Vec::new();
+        while let Some(mut entry) = archive.next_entry().await.unwrap() {
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents).await.unwrap();
+            let link_name = entry.link_name().unwrap_or_default();
+            entries.push((
+                entry.path_lossy(),
+                entry.header().entry_type(),
+                String::from_utf8_lossy(&link_name).into_owned(),
+                contents,
+            ));
+        }
+        entries
+    }
+
+    let regular = EntryType::Regular;
+    let dir = EntryType::Directory;
+    let entry = |path: &str, kind, link_name: &str, contents: &str| {
+        (path.into(), kind, link_name.into(), contents.into())
+    };
+
+    let mut data = Vec::new();
+    let mut archive = Archive::new(&mut data);
+    archive.set_follow_symlinks(false);
+    archive.append_dir_all("root", &src).await.unwrap();
+    archive.finish().await.unwrap();
+
+    let expected = [
+        entry("root", dir, "", ""),
+        entry("root/a.txt", regular, "", "hello"),
+        entry("root/b", dir, "", ""),
+        entry("root/b/c.txt", regular, "", "world!"),
+        entry("root/b/h", EntryType::Link, "root/a.txt", ""),
+        entry("root/l", EntryType::Symlink, "a.txt", ""),
+    ];
+    assert_eq!(list(&data).await, expected);
+
+    let mut archive = Archive::new(io::Cursor::new(&data));
+    while let Some(mut entry) = archive.next_entry().await.unwrap() {
+        assert_eq!(entry.uid().unwrap(), u64::from(uid));
+        if entry.path_lossy() == "root/b/c.txt" {
+            assert_eq!(entry.header().mode().unwrap(), 0o600);
+            assert_eq!(entry.mtime().unwrap(), mtime);
+        }
+        entry.skip().await.unwrap();
+    }
+
+    // Following symlinks archives what they point to, and the same tree
+    // always makes the same archive.
+    let mut followed = Vec::new();
+    for _ in 0..2 {
+        let mut data = Vec::new();
+        let mut archive = Archive::new(&mut data);
+        archive.append_dir_all("", &src).await.unwrap();
+        archive.finish().await.unwrap();
+        followed.push(data);
+    }
+    assert_eq!(followed[0], followed[1]);
+    let expected = [
+        entry("a.txt", regular, "", "hello"),
+        entry("b", dir, "", ""),
+        entry("b/c.txt", regular, "", "world!"),
+        entry("b/h", EntryType::Link, "a.txt", ""),
+        entry("l", EntryType::Link, "a.txt", ""),
+    ];
+    assert_eq!(list(&followed[0]).await, expected);
+
+    // Unless that leads around in circles.
+    symlink("..", src.join("b/loop")).unwrap();
+    let mut archive = Archive::new(Vec::new());
+    let err = archive.append_dir_all("", &src).await.unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn header_builder() {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::{EntryType, Format, HeaderBuilder, HeaderKind};
+
+    let mtime = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let atime = UNIX_EPOCH + Duration::from_secs(2_000_000);
+    let ctime = UNIX_EPOCH + Duration::from_secs(3_000_000);
+
+    let header = HeaderBuilder::new("dir/file")
+        .size(1000)
+        .mode(0o600)
+        .uid(1000)
+        .gid(100)
+        .username("user")
+        .groupname("group")
+        .mtime(mtime)
+        .build()
+        .unwrap();
+    assert_eq!(header.path_bytes().as_ref(), b"dir/file");
+    assert_eq!(header.entry_type(), EntryType::Regular);
+    assert_eq!(header.size().unwrap(), 1000);
+    assert_eq!(header.mode().unwrap(), 0o600);
+    assert_eq!(header.uid().unwrap(), 1000);
+    assert_eq!(header.gid().unwrap(), 100);
+    assert_eq!(header.username().unwrap(), Some("user"));
+    assert_eq!(header.groupname().unwrap(), Some("group"));
+    assert_eq!(header.mtime().unwrap(), 1_000_000);
+    assert!(header.cksum().unwrap() > 0);
+
+    // Anything that needs extensions must be added to an archive.
+    let long_path = "long/".repeat(40) + "path";
+    let builder = HeaderBuilder::new(&long_path).atime(atime).ctime(ctime);
+    let err = builder.build().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    HeaderBuilder::new("file").atime(atime).build().unwrap_err();
+    HeaderBuilder::new("file")
+        .atime(atime)
+        .format(Format::Gnu)
+        .build()
+        .unwrap();
+
+    let mut data = Vec::new();
+    let mut archive = Archive::new(&mut data);
+    archive.add_entry_with_builder(&builder).await.unwrap();
+    let builder = builder.format(Format::Gnu);
+    archive.add_entry_with_builder(&builder).await.unwrap();
+    let builder = HeaderBuilder::new("link")
+        .kind(EntryType::Symlink)
+        .link_name("target");
+    archive.add_entry_with_builder(&builder).await.unwrap();
+    let builder = HeaderBuilder::new("dev").kind(EntryType::Char).device(1, 3);
+    archive.add_entry_with_builder(&builder).await.unwrap();
+    archive.finish().await.unwrap();
+
+    let mut archive = Archive::new(io::Cursor::new(&data));
+    for kind in [HeaderKind::Pax, HeaderKind::Gnu] {
+        let mut entry = archive.next_entry().await.unwrap().unwrap();
+        assert_eq!(entry.kind(), kind);
+        assert_eq!(entry.path_lossy(), long_path);
+        assert_eq!(entry.atime().unwrap(), Some(atime));
+        assert_eq!(entry.ctime().unwrap(), Some(ctime));
+        entry.skip().await.unwrap();
+    }
+    let mut entry = archive.next_entry().await.unwrap().unwrap();
+    assert_eq!(entry.link_name().unwrap().as_ref(), b"target");
+    entry.skip().await.unwrap();
+    let mut entry = archive.next_entry().await.unwrap().unwrap();
+    assert_eq!(entry.header().device_major().unwrap(), Some(1));
+    assert_eq!(entry.header().device_minor().unwrap(), Some(3));
+    entry.skip().await.unwrap();
+    assert!(archive.next_entry().await.unwrap().is_none());
+}
+
+#[test]
+fn header_builder_from_metadata() {
+    use crate::{EntryType, HeaderBuilder};
+
+    let root = std::env::temp_dir().join(format!("tario-builder-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("file"), b"hello").unwrap();
+
+    let meta = std::fs::metadata(root.join("file")).unwrap();
+    let header = HeaderBuilder::from_metadata("file", &meta)
+        .unwrap()
+        .build()
+        .unwrap();
+    assert_eq!(header.entry_type(), EntryType::Regular);
+    assert_eq!(header.size().unwrap(), 5);
+    assert_eq!(
+        header.mtime().unwrap(),
+        meta.modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    );
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        assert_eq!(header.mode().unwrap(), meta.mode() & 0o7777);
+        assert_eq!(header.uid().unwrap(), u64::from(meta.uid()));
+    }
+
+    let meta = std::fs::metadata(&root).unwrap();
+    let builder = HeaderBuilder::from_metadata("dir", &meta).unwrap();
+    assert_eq!(builder.metadata().kind, EntryType::Directory);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[tokio::test]
+async fn reproducible() {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use crate::{EntryType, Format, HeaderBuilder};
+
+    let epoch = UNIX_EPOCH + Duration::new(1_000_000, 500);
+
+    async fn write(epoch: SystemTime, offset: u64, format: Format) -> Vec<u8> {
+        let mtime = epoch + Duration::new(offset, 123);
+        let mut data = Vec::new();
+        let mut archive = Archive::new(&mut data);
+        archive.set_reproducible(Some(epoch));
+        archive.set_format(format);
+
+        let builder = HeaderBuilder::new("file")
+            .size(5)
+            .mode(0o600 | (offset as u32) << 4)
+            .uid(1000 + offset)
+            .gid(1 << 32)
+            .username(format!("user{offset}"))
+            .groupname("g".repeat(40))
+            .mtime(mtime)
+            .atime(mtime)
+            .ctime(mtime);
+        let mut entry = archive.add_entry_with_builder(&builder).await.unwrap();
+        entry.write_all(b"hello").await.unwrap();
+
+        let builder = HeaderBuilder::new("dir")
+            .kind(EntryType::Directory)
+            .mode(0o700)
+            .mtime(mtime);
+        archive.add_entry_with_builder(&builder).await.unwrap();
+
+        let builder = HeaderBuilder::new("script").mode(0o700).mtime(mtime);
+        archive.add_entry_with_builder(&builder).await.unwrap();
+
+        // Plain headers are normalised too, and earlier mtimes are kept.
+        let mut header = make_entry_header("old", 0);
+        header.set_mtime(1000);
+        header.set_uid(1000 + offset);
+        header.set_cksum();
+        archive.add_entry(header).await.unwrap();
+
+        archive.finish().await.unwrap();
+        data
+    }
+
+    for format in [Format::Pax, Format::Gnu] {
+        let data = write(epoch, 1, format).await;
+        assert_eq!(data, write(epoch, 2, format).await);
+
+        let mut archive = Archive::new(io::Cursor::new(&data));
+        let expected = [
+            ("file", 0o644, 1_000_000),
+            ("dir", 0o755, 1_000_000),
+            ("script", 0o755, 1_000_000),
+            ("old", 0o644, 1000),
+        ];
+        for (path, mode, mtime) in expected {
+            let mut entry = archive.next_entry().await.unwrap().unwrap();
+            assert_eq!(entry.path_lossy(), path);
+            assert!(entry.pax_attributes().is_empty());
+            assert_eq!(entry.header().mode().unwrap(), mode);
+            assert_eq!(
+                entry.mtime().unwrap(),
+                UNIX_EPOCH + Duration::from_secs(mtime)
+            );
+            assert_eq!(entry.atime().unwrap(), None);
+            assert_eq!(entry.ctime().unwrap(), None);
+            assert_eq!(entry.uid().unwrap(), 0);
+            assert_eq!(entry.gid().unwrap(), 0);
+            assert_eq!(entry.username(), Some(&b""[..]));
+            assert_eq!(entry.groupname(), Some(&b""[..]));
+            entry.skip().await.unwrap();
+        }
+        assert!(archive.next_entry().await.unwrap().is_none());
+    }
+
+    // Headers are checked before they're normalised, which would set their
+    // checksum, and errors in normalising them poison the archive.
+    let mut header = make_entry_header("a", 0);
+    header.as_old_mut().cksum = [0; 8];
+    let mut archive = Archive::new(Vec::new());
+    archive.set_reproducible(Some(epoch));
+    let err = write_error(archive.add_entry(header).await.unwrap_err());
+    assert!(matches!(err, WriteError::UnfinalizedHeader));
+
+    let mut header = make_entry_header("a", 0);
+    header.as_old_mut().mtime = *b"xyz\0\0\0\0\0\0\0\0\0";
+    header.set_cksum();
+    let mut archive = Archive::new(Vec::new());
+    archive.set_reproducible(Some(epoch));
+    let err = write_error(archive.add_entry(header).await.unwrap_err());
+    assert!(matches!(err, WriteError::InvalidMtime));
+    let err = write_error(archive.finish().await.unwrap_err());
+    assert!(
+        matches!(&err, WriteError::Poisoned(cause) if matches!(**cause, WriteError::InvalidMtime))
+    );
+}
+
+#[cfg(feature = "compression")]
+#[tokio::test]
+async fn compress() {
+    use tokio::io::AsyncReadExt;
+
+    use crate::Compression;
+
+    async fn read_entry<R: tokio::io::AsyncRead + Unpin>(
+        archive: &mut Archive<R>,
+    ) -> Option<(String, Vec<u8>)> {
+        let mut entry = archive.next_entry().await.unwrap()?;
+        let mut data = Vec::new();
+        entry.read_to_end(&mut data).await.unwrap();
+        Some((entry.path_lossy(), data))
+    }
+
+    let expected = FILES
+        .iter()
+        .map(|(path, size)| (path.to_string(), make_entry_data(*size)[..*size].to_vec()))
+        .collect::<Vec<_>>();
+
+    let mut enabled = vec![Compression::None];
+    enabled.extend(cfg!(feature = "gzip").then_some(Compression::Gzip));
+    enabled.extend(cfg!(feature = "zstd").then_some(Compression::Zstd));
+    enabled.extend(cfg!(feature = "xz").then_some(Compression::Xz));
+    enabled.extend(cfg!(feature = "bzip2").then_some(Compression::Bzip2));
+
+    for compression in enabled {
+        for per_entry in [false, true] {
+            eprintln!("compression = {compression}, per_entry = {per_entry}");
+
+            let mut data = Vec::new();
+            let mut archive = Archive::new_compressed(&mut data, compression).unwrap();
+            archive.set_frame_per_entry(per_entry);
+            let mut offsets = Vec::new();
+            for (path, contents) in &expected {
+                offsets.push(archive.end_frame().await.unwrap());
+                let header = make_entry_header(path, contents.len());
+                let mut entry = archive.add_entry(header).await.unwrap();
+                // Flushing mid-entry is fine, if wasteful with frames.
+                entry.write_all(&contents[..10]).await.unwrap();
+                entry.flush().await.unwrap();
+                entry.write_all(&contents[10..]).await.unwrap();
+            }
+            archive.finish().await.unwrap();
+            assert_eq!(archive.get_ref().position(), data.len() as u64);
+
+            let mut archive = Archive::new_auto(io::Cursor::new(&data));
+            for entry in &expected {
+                assert_eq!(read_entry(&mut archive).await.as_ref(), Some(entry));
+            }
+            assert!(read_entry(&mut archive).await.is_none());
+            assert_eq!(archive.get_ref().compression(), Some(compression));
+
+            // Each entry can be read from the start of its frame.
+            assert_eq!(offsets[0], 0);
+            for (offset, entry) in offsets.iter().zip(&expected) {
+                let io = io::Cursor::new(&data[*offset as usize..]);
+                let mut archive = Archive::new_auto(io);
+                assert_eq!(read_entry(&mut archive).await.as_ref(), Some(entry));
+            }
+        }
+    }
+
+    // Compressions whose feature is not enabled are rejected.
+    #[cfg(not(feature = "xz"))]
+    {
+        let err = Archive::new_compressed(Vec::new(), Compression::Xz).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+}
+
+#[tokio::test]
+async fn offsets() {
+    use crate::{EntryMetadata, Format, SparseRegion};
+
+    let long_path = "long".repeat(50);
+    let meta = EntryMetadata::default();
+    let regions = [SparseRegion {
+        offset: 1000,
+        len: 100,
+    }];
+
+    for format in [Format::Pax, Format::Gnu] {
+        let mut data = Vec::new();
+        let mut archive = Archive::new(&mut data);
+        archive.set_format(format);
+        let mut written = Vec::new();
+        for (path, size) in [("short", 10), (long_path.as_str(), 1000), ("empty", 0)] {
+            let start = archive.position();
+            let mut entry = archive
+                .add_entry_with_metadata(path, size as u64, &meta)
+                .await
+                .unwrap();
+            assert_eq!(entry.header_offset(), start);
+            entry.write_all(&vec![1; size]).await.unwrap();
+            written.push((entry.header_offset(), entry.data_offset()));
+        }
+        let mut entry = archive
+            .add_sparse_entry("sparse", &regions, 2000, &meta)
+            .await
+            .unwrap();
+        entry.write_all(&[2; 100]).await.unwrap();
+        written.push((entry.header_offset(), entry.data_offset()));
+        archive.finish().await.unwrap();
+        assert_eq!(archive.position(), data.len() as u64);
+
+        // The short entry has only its header, the long one extensions too.
+        assert_eq!(written[0], (0, BLOCK_SIZE as u64));
+        assert_eq!(written[1].0, 2 * BLOCK_SIZE as u64);
+        assert!(written[1].1 >= written[1].0 + 3 * BLOCK_SIZE as u64);
+        assert_eq!(&data[written[3].1 as usize..][..100], &[2; 100]);
+
+        let mut archive = Archive::new(io::Cursor::new(&data));
+        let mut read = Vec::new();
+        while let Some(mut entry) = archive.next_entry().await.unwrap() {
+            read.push((entry.header_offset(), entry.data_offset()));
+            assert_eq!(archive_position(&entry), entry.data_offset());
+            entry.skip().await.unwrap();
+        }
+        assert_eq!(read, written);
+        assert_eq!(archive.position(), data.len() as u64);
+    }
+}
+
+fn archive_position<T>(entry: &crate::Entry<'_, T>) -> u64 {
+    entry.archive.position()
+}